@@ -0,0 +1,388 @@
+use actix::{
+    Actor, ActorContext, AsyncContext, Context, Handler, Message, MessageResult, Recipient,
+};
+use foundations::telemetry::log;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::{
+    bolas::{BolasArena, MAX_TETHERED_BOLAS},
+    delta::ArenaSnapshot,
+    physics::PhysicsParams,
+    protocol::{ErrorCode, MessageId},
+    websocket::ClientMessage,
+};
+
+/// Longest stretch of simulation time the arena will try to catch up on
+/// after falling behind, any more than this is dropped so that a stalled
+/// arena doesn't spend ages stepping through time nobody saw
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+/// Snapshot of the arena state, broadcast to every client in the arena on
+/// each refresh. Each client works out what to send on from it, since what
+/// it needs depends on what it's been sent before.
+#[derive(Clone, Message)]
+#[rtype(result = "()")]
+pub(crate) struct ArenaStateUpdate(pub(crate) Arc<ArenaSnapshot>);
+
+/// Adds a client to the arena, replying with the physics the arena is
+/// simulated with so the client can be greeted straight away
+#[derive(Message)]
+#[rtype(result = "PhysicsParams")]
+pub(crate) struct Connect {
+    pub(crate) client_id: Uuid,
+    pub(crate) recipient: Recipient<ArenaStateUpdate>,
+    pub(crate) rejections: Recipient<CommandRejected>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct Disconnect {
+    pub(crate) client_id: Uuid,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct ClientCommand {
+    pub(crate) client_id: Uuid,
+
+    /// Id the client gave the message, if any, sent back to it should the
+    /// arena refuse the message
+    pub(crate) id: Option<MessageId>,
+    pub(crate) message: ClientMessage,
+}
+
+/// Sent back to a client whose message the arena couldn't act on, like one
+/// referring to a bola which has since been deleted
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct CommandRejected {
+    pub(crate) id: Option<MessageId>,
+    pub(crate) code: ErrorCode,
+    pub(crate) message: String,
+}
+
+/// Reason the arena refused a client's message, with a description of what
+/// it couldn't do
+type Rejection = (ErrorCode, &'static str);
+
+struct ArenaClient {
+    recipient: Recipient<ArenaStateUpdate>,
+    rejections: Recipient<CommandRejected>,
+    canvas_dimensions: Option<(i32, i32)>,
+}
+
+/// Actor owning a single `BolasArena` shared between all of the clients
+/// connected to it. Clients are added and removed by the `ArenaRegistry`,
+/// and the actor stops once its last client has disconnected.
+pub(crate) struct ArenaActor {
+    arena: BolasArena,
+    clients: HashMap<Uuid, ArenaClient>,
+
+    /// Interval at which the arena state is sent to clients
+    refresh_rate: Duration,
+
+    /// Simulation time owed to the arena which hasn't been stepped through
+    /// yet, the physics runs in fixed timesteps so this carries over any
+    /// remainder smaller than a timestep, or the time lost if the actor is
+    /// scheduled late
+    accumulator: Duration,
+    last_step: Instant,
+
+    /// Client allowed to clear the arena and change its physics, which is
+    /// the one that created the arena until it leaves, when it's handed on
+    /// to one of the clients still connected
+    owner: Option<Uuid>,
+}
+
+impl ArenaActor {
+    pub(crate) fn new(arena: BolasArena, refresh_rate: Duration) -> Self {
+        Self {
+            arena,
+            clients: Default::default(),
+            refresh_rate,
+            accumulator: Duration::ZERO,
+            last_step: Instant::now(),
+            owner: None,
+        }
+    }
+
+    fn step(&mut self) {
+        let now = Instant::now();
+        self.accumulator += now - self.last_step;
+        self.last_step = now;
+
+        if self.accumulator > MAX_CATCH_UP {
+            log::debug!("Bolas arena fell behind, dropping simulation time"; "arena" => %self.arena.get_id(), "behind" => ?self.accumulator);
+            self.accumulator = MAX_CATCH_UP;
+        }
+
+        let timestep = self.arena.get_timestep();
+        while self.accumulator >= timestep {
+            self.arena.update_state();
+            self.accumulator -= timestep;
+        }
+    }
+
+    /// Sends a snapshot of the arena state to every client
+    fn refresh(&mut self) {
+        let snapshot = Arc::new(self.arena.snapshot());
+        for client in self.clients.values() {
+            client
+                .recipient
+                .do_send(ArenaStateUpdate(Arc::clone(&snapshot)));
+        }
+    }
+
+    /// Clients may be looking at canvases of different sizes, so the arena is
+    /// bounded by the smallest of them to keep every bola visible to everyone
+    fn update_canvas_dimensions(&mut self) {
+        let dimensions = self
+            .clients
+            .values()
+            .filter_map(|c| c.canvas_dimensions)
+            .reduce(|(h1, w1), (h2, w2)| (h1.min(h2), w1.min(w2)));
+
+        if let Some((height, width)) = dimensions {
+            self.arena.set_canvas_dimensions(height, width);
+        }
+    }
+}
+
+impl Actor for ArenaActor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        log::info!("Created new bolas arena"; "arena" => %self.arena.get_id());
+        self.last_step = Instant::now();
+        ctx.run_interval(self.arena.get_timestep(), |act, _| act.step());
+        ctx.run_interval(self.refresh_rate, |act, _| act.refresh());
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        log::info!("Closed bolas arena"; "arena" => %self.arena.get_id());
+    }
+}
+
+impl Handler<Connect> for ArenaActor {
+    type Result = MessageResult<Connect>;
+
+    fn handle(&mut self, msg: Connect, _: &mut Self::Context) -> Self::Result {
+        log::debug!("Client joined bolas arena"; "arena" => %self.arena.get_id(), "client" => %msg.client_id);
+        self.owner.get_or_insert(msg.client_id);
+        self.clients.insert(
+            msg.client_id,
+            ArenaClient {
+                recipient: msg.recipient,
+                rejections: msg.rejections,
+                canvas_dimensions: None,
+            },
+        );
+
+        MessageResult(self.arena.physics())
+    }
+}
+
+impl Handler<Disconnect> for ArenaActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, ctx: &mut Self::Context) {
+        log::debug!("Client left bolas arena"; "arena" => %self.arena.get_id(), "client" => %msg.client_id);
+        self.clients.remove(&msg.client_id);
+        self.arena.release_grabs(msg.client_id);
+
+        if self.clients.is_empty() {
+            ctx.stop();
+            return;
+        }
+
+        if self.owner == Some(msg.client_id) {
+            self.owner = self.clients.keys().next().copied();
+            log::debug!("Handed bolas arena on to another client"; "arena" => %self.arena.get_id(), "owner" => ?self.owner);
+        }
+
+        self.update_canvas_dimensions();
+    }
+}
+
+impl Handler<ClientCommand> for ArenaActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ClientCommand, _: &mut Self::Context) {
+        let Err((code, message)) = self.apply_command(msg.client_id, msg.message) else {
+            return;
+        };
+
+        log::warn!(
+            "Rejected message from client";
+            "arena" => %self.arena.get_id(),
+            "client" => %msg.client_id,
+            "id" => ?msg.id,
+            "code" => ?code,
+            "reason" => message,
+        );
+
+        if let Some(client) = self.clients.get(&msg.client_id) {
+            client.rejections.do_send(CommandRejected {
+                id: msg.id,
+                code,
+                message: message.to_owned(),
+            });
+        }
+    }
+}
+
+impl ArenaActor {
+    fn apply_command(&mut self, client_id: Uuid, message: ClientMessage) -> Result<(), Rejection> {
+        match message {
+            ClientMessage::SetCanvasDimensions { height, width } => {
+                // The arena is bounded by the smallest canvas, so an empty one
+                // would take the walls away for every client
+                if height <= 0 || width <= 0 {
+                    return Err((
+                        ErrorCode::InvalidArgument,
+                        "Canvas dimensions must be positive",
+                    ));
+                }
+
+                log::debug!(
+                    "Updating canvas dimensions";
+                    "arena" => %self.arena.get_id(),
+                    "client" => %client_id,
+                    "height" => height,
+                    "width" => width,
+                );
+
+                if let Some(client) = self.clients.get_mut(&client_id) {
+                    client.canvas_dimensions = Some((height, width));
+                }
+
+                self.update_canvas_dimensions();
+            }
+            ClientMessage::NewBola(bola) => {
+                log::debug!("Adding new bola"; "arena" => %self.arena.get_id(), "client" => %client_id, "bola" => ?bola);
+                self.arena
+                    .add_bola(bola)
+                    .map_err(|code| (code, "Failed to add bola"))?;
+            }
+            ClientMessage::NewTetheredBolas { bolas, length } => {
+                if !(2..=MAX_TETHERED_BOLAS).contains(&bolas.len()) {
+                    return Err((
+                        ErrorCode::InvalidArgument,
+                        "Wrong number of bolas to tether together",
+                    ));
+                }
+
+                log::debug!("Adding new tethered bolas"; "arena" => %self.arena.get_id(), "client" => %client_id, "bolas" => ?bolas, "length" => ?length);
+                self.arena
+                    .add_tethered_bolas(bolas, length)
+                    .map_err(|code| (code, "Failed to add tethered bolas"))?;
+            }
+            ClientMessage::NewSpring {
+                one,
+                two,
+                length,
+                stiffness,
+                damping,
+            } => {
+                log::debug!("Adding new spring"; "arena" => %self.arena.get_id(), "client" => %client_id, "one" => one, "two" => two, "length" => ?length, "stiffness" => stiffness, "damping" => damping);
+                self.arena
+                    .add_spring(one, two, length, stiffness, damping)
+                    .map_err(|code| (code, "Failed to add spring"))?;
+            }
+            ClientMessage::NewDistanceJoint { one, two, length } => {
+                log::debug!("Adding new distance joint"; "arena" => %self.arena.get_id(), "client" => %client_id, "one" => one, "two" => two, "length" => ?length);
+                self.arena
+                    .add_distance_joint(one, two, length)
+                    .map_err(|code| (code, "Failed to add distance joint"))?;
+            }
+            ClientMessage::NewPinJoint {
+                bola,
+                anchor,
+                length,
+            } => {
+                log::debug!("Adding new pin joint"; "arena" => %self.arena.get_id(), "client" => %client_id, "bola" => bola, "anchor" => ?anchor, "length" => ?length);
+                self.arena
+                    .add_pin_joint(bola, anchor, length)
+                    .map_err(|code| (code, "Failed to add pin joint"))?;
+            }
+            ClientMessage::DeleteBola { bola } => {
+                log::debug!("Deleting bola"; "arena" => %self.arena.get_id(), "client" => %client_id, "bola" => bola);
+                self.arena
+                    .delete_bola(bola)
+                    .map_err(|code| (code, "Failed to delete bola"))?;
+            }
+            ClientMessage::ClearArena => {
+                if self.owner != Some(client_id) {
+                    return Err((
+                        ErrorCode::NotPermitted,
+                        "Only the client which owns the arena may clear it",
+                    ));
+                }
+
+                log::debug!("Clearing arena"; "arena" => %self.arena.get_id(), "client" => %client_id);
+                self.arena.clear();
+            }
+            ClientMessage::GrabBola { bola } => {
+                log::debug!("Grabbing bola"; "arena" => %self.arena.get_id(), "client" => %client_id, "bola" => bola);
+                self.arena
+                    .grab_bola(bola, client_id)
+                    .map_err(|code| (code, "Failed to grab bola"))?;
+            }
+            ClientMessage::DragBola { bola, position } => {
+                self.arena
+                    .drag_bola(bola, client_id, position)
+                    .map_err(|code| (code, "Failed to drag bola"))?;
+            }
+            ClientMessage::ReleaseBola { bola, velocity } => {
+                log::debug!("Releasing bola"; "arena" => %self.arena.get_id(), "client" => %client_id, "bola" => bola, "velocity" => ?velocity);
+                self.arena
+                    .release_bola(bola, client_id, velocity)
+                    .map_err(|code| (code, "Failed to release bola"))?;
+            }
+            ClientMessage::NewObstacle(obstacle) => {
+                log::debug!("Adding new obstacle"; "arena" => %self.arena.get_id(), "client" => %client_id, "obstacle" => ?obstacle);
+                self.arena
+                    .add_obstacle(obstacle)
+                    .map_err(|code| (code, "Failed to add obstacle"))?;
+            }
+            ClientMessage::SetForceFields(force_fields) => {
+                if self.owner != Some(client_id) {
+                    return Err((
+                        ErrorCode::NotPermitted,
+                        "Only the client which owns the arena may set its force fields",
+                    ));
+                }
+
+                log::debug!("Updating force fields"; "arena" => %self.arena.get_id(), "client" => %client_id, "force_fields" => ?force_fields);
+                self.arena.set_force_fields(force_fields);
+            }
+            ClientMessage::SetMaterials(materials) => {
+                if self.owner != Some(client_id) {
+                    return Err((
+                        ErrorCode::NotPermitted,
+                        "Only the client which owns the arena may set its materials",
+                    ));
+                }
+
+                log::debug!("Updating materials"; "arena" => %self.arena.get_id(), "client" => %client_id, "materials" => ?materials);
+                self.arena.set_materials(materials);
+            }
+            ClientMessage::SetBoundaries(boundaries) => {
+                if self.owner != Some(client_id) {
+                    return Err((
+                        ErrorCode::NotPermitted,
+                        "Only the client which owns the arena may set its boundaries",
+                    ));
+                }
+
+                log::debug!("Updating boundaries"; "arena" => %self.arena.get_id(), "client" => %client_id, "boundaries" => ?boundaries);
+                self.arena.set_boundaries(boundaries);
+            }
+        }
+
+        Ok(())
+    }
+}
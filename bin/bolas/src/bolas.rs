@@ -1,85 +1,493 @@
-use crate::collisions::{Collision, CollisionDetectionAlgorithm, CollisionDetector};
+use crate::collisions::{bolas_collide, new_broad_phase, time_since_impact, BroadPhase, Collision};
+use crate::constraints::{solve_constraints, Constraint, MAX_SPRING_DAMPING, MAX_SPRING_STIFFNESS};
+use crate::delta::ArenaSnapshot;
 use crate::metrics::metrics;
+use crate::obstacles::Obstacle;
+use crate::physics::{
+    normalize_coefficient, Boundaries, BoundaryMode, ForceFields, Integrator, Materials,
+    PhysicsParams,
+};
+use crate::protocol::{deserialize_coordinate, serialize_coordinate, ErrorCode};
+use crate::quadtree::Aabb;
+use crate::settings::BolasConfig;
 use foundations::telemetry::log;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::time::Duration;
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub(crate) struct Point {
+    #[serde(
+        serialize_with = "serialize_coordinate",
+        deserialize_with = "deserialize_coordinate"
+    )]
     pub(crate) x: f64,
+
+    #[serde(
+        serialize_with = "serialize_coordinate",
+        deserialize_with = "deserialize_coordinate"
+    )]
     pub(crate) y: f64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct Vector {
+    #[serde(
+        serialize_with = "serialize_coordinate",
+        deserialize_with = "deserialize_coordinate"
+    )]
     pub(crate) vel_x: f64,
+
+    #[serde(
+        serialize_with = "serialize_coordinate",
+        deserialize_with = "deserialize_coordinate"
+    )]
     pub(crate) vel_y: f64,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+/// Factor by which the velocity users release balls with (length of pull
+/// line in pixels) is divided to get the velocity in pixels per second.
+/// I've found this makes the experience look reasonable.
+const VELOCITY_SCALING_FACTOR: f64 = 0.256;
+
+/// Most times a bola can bounce between opposite edges of the arena within
+/// a single step, only reachable when the arena is tiny or bolas are very
+/// fast, beyond which the bola is simply clamped inside the arena
+const MAX_BOUNCES_PER_STEP: usize = 8;
+
+/// Overlap between two bolas, in pixels, which is tolerated without being
+/// corrected, so that bolas resting against each other don't jitter
+const PENETRATION_SLOP: f64 = 0.5;
+
+/// Fraction of the overlap between two bolas corrected each substep, which
+/// is less than all of it so stacks of bolas settle rather than jostle
+const PENETRATION_CORRECTION: f64 = 0.8;
+
+/// Radius given to bolas for which the client didn't request one
+pub(crate) const DEFAULT_BOLA_RADIUS: f64 = 20.;
+
+/// Bounds on the radius a client may request for a new bola
+const MIN_BOLA_RADIUS: f64 = 2.;
+const MAX_BOLA_RADIUS: f64 = 200.;
+
+/// Bounds on the mass a client may request for a new bola, which keep its
+/// inverse mass, and so its share of any correction, finite
+const MIN_BOLA_MASS: f64 = 0.001;
+const MAX_BOLA_MASS: f64 = 1000.;
+
+/// Most bolas a client may tie together into a single chain
+pub(crate) const MAX_TETHERED_BOLAS: usize = 16;
+
+/// Most bolas an arena may contain, every one of which is moved and checked
+/// for collisions several times a timestep
+pub(crate) const MAX_BOLAS: usize = 1024;
+
+/// Most static obstacles an arena may contain
+pub(crate) const MAX_OBSTACLES: usize = 256;
+
+/// Most ropes, springs and joints an arena may contain, each of which is
+/// solved several times every substep
+pub(crate) const MAX_CONSTRAINTS: usize = 512;
+
+/// Fastest a client may throw a bola it's been dragging, or send a new bola
+/// off at, in pixels per second
+const MAX_THROW_SPEED: f64 = 10000.;
+
+/// Longest rope, spring or joint a client may tie between a pair of bolas,
+/// or between a bola and a fixed point
+const MAX_CONSTRAINT_LENGTH: f64 = 1000.;
+
+/// Furthest from the origin, in pixels along either axis, a bola may get
+/// while the canvas dimensions aren't known yet and there are no edges to
+/// keep it in, so that bolas drifting off under gravity stay well within
+/// the coordinates the broad phases can index
+const MAX_COORDINATE: f64 = 100000.;
+
+/// Server-assigned identifier of a bola, unique within its arena and never
+/// reused, so clients can keep track of bolas as others are removed
+pub(crate) type BolaId = u64;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub(crate) struct Bola {
+    #[serde(skip_deserializing, rename = "i")]
+    pub(crate) id: BolaId,
+
     #[serde(rename = "c")]
     pub(crate) center: Point,
 
-    #[serde(skip_serializing, rename = "v")]
+    /// Sent to clients in pixels per second so they can move bolas along
+    /// between updates, but received from them as the length of the line
+    /// the bola was pulled back by when thrown
+    #[serde(rename = "v")]
     pub(crate) velocity: Vector,
+
+    #[serde(
+        default = "default_bola_radius",
+        rename = "r",
+        serialize_with = "serialize_coordinate"
+    )]
+    pub(crate) radius: f64,
+
+    /// Mass of the bola, if the client doesn't provide one (or provides a
+    /// non-positive one) it's derived from the area of the bola, such that
+    /// a bola with the default radius has a mass of 1
+    #[serde(default, skip_serializing, rename = "m")]
+    pub(crate) mass: f64,
+
+    /// Overrides the arena's drag for this bola
+    #[serde(default, skip_serializing, rename = "d")]
+    pub(crate) drag: Option<f64>,
+
+    /// Overrides the arena's rolling friction for this bola
+    #[serde(default, skip_serializing, rename = "f")]
+    pub(crate) rolling_friction: Option<f64>,
+
+    /// Overrides the arena's coefficients of restitution, for both wall
+    /// bounces and collisions with other bolas, for this bola
+    #[serde(default, skip_serializing, rename = "e")]
+    pub(crate) restitution: Option<f64>,
+
+    /// Set while a client is dragging the bola around, during which it
+    /// ignores forces, edges and obstacles and just follows the pointer
+    #[serde(skip)]
+    pub(crate) grab: Option<Grab>,
+}
+
+#[derive(Clone, Debug)]
+pub(crate) struct Grab {
+    client_id: Uuid,
+
+    /// Where the client last dragged the bola to
+    target: Point,
+}
+
+fn default_bola_radius() -> f64 {
+    DEFAULT_BOLA_RADIUS
 }
 
 impl Bola {
-    fn update_position(&mut self, canvas_height: f64, canvas_width: f64) {
-        let mut new_center_x = self.center.x + self.velocity.vel_x;
-        let mut new_center_y = self.center.y + self.velocity.vel_y;
+    fn normalize(&mut self) {
+        for coordinate in [&mut self.center.x, &mut self.center.y] {
+            if !coordinate.is_finite() {
+                *coordinate = 0.;
+            }
+            *coordinate = coordinate.clamp(-MAX_COORDINATE, MAX_COORDINATE);
+        }
 
-        if new_center_x < 0. {
-            new_center_x = -new_center_x;
-            self.velocity.vel_x = -self.velocity.vel_x;
+        for velocity in [&mut self.velocity.vel_x, &mut self.velocity.vel_y] {
+            *velocity = normalize_coefficient(*velocity, -MAX_THROW_SPEED, MAX_THROW_SPEED, 0.);
         }
-        if new_center_y < 0. {
-            new_center_y = -new_center_y;
-            self.velocity.vel_y = -self.velocity.vel_y;
+
+        if !self.radius.is_finite() {
+            self.radius = DEFAULT_BOLA_RADIUS;
         }
+        self.radius = self.radius.clamp(MIN_BOLA_RADIUS, MAX_BOLA_RADIUS);
 
-        if new_center_x > canvas_width {
-            new_center_x = canvas_width - (new_center_x - canvas_width);
-            self.velocity.vel_x = -self.velocity.vel_x;
+        if !self.mass.is_finite() || self.mass <= 0. {
+            self.mass = (self.radius / DEFAULT_BOLA_RADIUS).powf(2.);
         }
+        self.mass = self.mass.clamp(MIN_BOLA_MASS, MAX_BOLA_MASS);
 
-        if new_center_y > canvas_height {
-            new_center_y = canvas_height - (new_center_y - canvas_height);
-            self.velocity.vel_y = -self.velocity.vel_y;
+        self.drag = self
+            .drag
+            .map(|d| normalize_coefficient(d, 0., f64::MAX, 0.));
+        self.rolling_friction = self
+            .rolling_friction
+            .map(|f| normalize_coefficient(f, 0., f64::MAX, 0.));
+        self.restitution = self
+            .restitution
+            .map(|r| normalize_coefficient(r, 0., 1., 1.));
+    }
+
+    /// Grabbed bolas are moved only by the client dragging them, so nothing
+    /// else in the arena can push them around, as if they had infinite mass
+    pub(crate) fn inverse_mass(&self) -> f64 {
+        if self.grab.is_some() {
+            0.
+        } else {
+            1. / self.mass
+        }
+    }
+
+    /// The arena's materials with this bola's overrides applied
+    fn materials(&self, arena_materials: &Materials) -> Materials {
+        Materials {
+            drag: self.drag.unwrap_or(arena_materials.drag),
+            rolling_friction: self
+                .rolling_friction
+                .unwrap_or(arena_materials.rolling_friction),
+            wall_restitution: self.restitution.unwrap_or(arena_materials.wall_restitution),
+            restitution: self.restitution.unwrap_or(arena_materials.restitution),
+        }
+    }
+
+    /// Slows the bola down for air drag and rolling friction over a
+    /// timestep of the given length
+    fn apply_friction(&mut self, materials: &Materials, dt: f64) {
+        let decay = (-materials.drag * dt).exp();
+        self.velocity.vel_x *= decay;
+        self.velocity.vel_y *= decay;
+
+        // Rolling friction slows the bola by a constant amount each step,
+        // but never so much that it starts rolling backwards
+        let speed = (self.velocity.vel_x.powf(2.) + self.velocity.vel_y.powf(2.)).sqrt();
+        if speed > 0. {
+            let slowed_speed = (speed - materials.rolling_friction * dt).max(0.);
+            self.velocity.vel_x *= slowed_speed / speed;
+            self.velocity.vel_y *= slowed_speed / speed;
         }
+    }
+
+    /// Gives a grabbed bola the velocity which takes it to where it's being
+    /// dragged to over the given length of time, so it moves there steadily
+    /// across the substeps of a timestep and bolas it hits on the way are
+    /// knocked away as if by a bola moving at the speed it's dragged
+    fn follow_grab(&mut self, dt: f64) {
+        let Some(grab) = &self.grab else {
+            return;
+        };
+
+        self.velocity.vel_x = (grab.target.x - self.center.x) / dt;
+        self.velocity.vel_y = (grab.target.y - self.center.y) / dt;
+    }
+
+    /// Length of the bola's velocity, in pixels per second
+    fn speed(&self) -> f64 {
+        (self.velocity.vel_x.powf(2.) + self.velocity.vel_y.powf(2.)).sqrt()
+    }
+
+    /// Bounding box of everywhere the bola has been over the last `dt`
+    /// seconds, given that it's been moving at its current velocity
+    fn swept_bounds(&self, dt: f64) -> Aabb {
+        let (start_x, start_y) = (
+            self.center.x - self.velocity.vel_x * dt,
+            self.center.y - self.velocity.vel_y * dt,
+        );
+
+        Aabb {
+            min_x: start_x.min(self.center.x) - self.radius,
+            min_y: start_y.min(self.center.y) - self.radius,
+            max_x: start_x.max(self.center.x) + self.radius,
+            max_y: start_y.max(self.center.y) + self.radius,
+        }
+    }
+
+    /// Moves the bola along its velocity for the given length of time,
+    /// ignoring the edges of the arena and any forces acting on it
+    fn advance(&mut self, dt: f64) {
+        self.center.x += self.velocity.vel_x * dt;
+        self.center.y += self.velocity.vel_y * dt;
+    }
+
+    /// Pushes the bola out of an obstacle it has sunk into and reflects its
+    /// velocity off the obstacle's surface, if it's moving into it
+    fn bounce_off_obstacle(&mut self, obstacle: &Obstacle, restitution: f64) {
+        let Some(contact) = obstacle.contact(self) else {
+            return;
+        };
+
+        let (normal_x, normal_y) = contact.normal;
+        self.center.x += normal_x * contact.penetration;
+        self.center.y += normal_y * contact.penetration;
+
+        let speed = self.velocity.vel_x * normal_x + self.velocity.vel_y * normal_y;
+        if speed < 0. {
+            self.velocity.vel_x -= (1. + restitution) * speed * normal_x;
+            self.velocity.vel_y -= (1. + restitution) * speed * normal_y;
+        }
+    }
+
+    /// Handles the bola reaching any of the edges of the arena according to
+    /// the edge's boundary mode. Returns false if the bola left the arena
+    /// through an open edge.
+    fn bounce_off_walls(
+        &mut self,
+        canvas_height: f64,
+        canvas_width: f64,
+        boundaries: &Boundaries,
+        restitution: f64,
+    ) -> bool {
+        let horizontal = bounce_off_edges(
+            self.center.x,
+            self.velocity.vel_x,
+            canvas_width,
+            (boundaries.left, boundaries.right),
+            restitution,
+        );
+        if !self.apply_edge_outcome(horizontal, true) {
+            return false;
+        }
+
+        let vertical = bounce_off_edges(
+            self.center.y,
+            self.velocity.vel_y,
+            canvas_height,
+            (boundaries.top, boundaries.bottom),
+            restitution,
+        );
+        self.apply_edge_outcome(vertical, false)
+    }
+
+    fn apply_edge_outcome(&mut self, outcome: EdgeOutcome, horizontal: bool) -> bool {
+        let (position, velocity) = if horizontal {
+            (&mut self.center.x, &mut self.velocity.vel_x)
+        } else {
+            (&mut self.center.y, &mut self.velocity.vel_y)
+        };
+
+        match outcome {
+            EdgeOutcome::Inside(p, v) => {
+                *position = p;
+                *velocity = v;
+            }
+            EdgeOutcome::Stuck(p) => {
+                *position = p;
+                self.velocity.vel_x = 0.;
+                self.velocity.vel_y = 0.;
+            }
+            EdgeOutcome::Left => return false,
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+impl Bola {
+    /// Bola at rest at the given point, with the mass it would be given if
+    /// a client created it with no mass
+    pub(crate) fn at(x: f64, y: f64, radius: f64) -> Self {
+        Self {
+            id: 0,
+            center: Point { x, y },
+            velocity: Vector {
+                vel_x: 0.,
+                vel_y: 0.,
+            },
+            radius,
+            mass: (radius / DEFAULT_BOLA_RADIUS).powf(2.),
+            drag: None,
+            rolling_friction: None,
+            restitution: None,
+            grab: None,
+        }
+    }
+}
+
+/// Where a bola ends up along one axis after reaching the edges of the arena
+enum EdgeOutcome {
+    /// Still inside the arena, at the given position and velocity
+    Inside(f64, f64),
+    /// Stopped dead against a sticky edge at the given position
+    Stuck(f64),
+    /// Left the arena through an open edge
+    Left,
+}
+
+/// Share of a correction between a pair of bolas each of them takes, which
+/// is proportional to the other's share of their total mass, so a light bola
+/// is moved more than a heavy one. Returns `None` if both bolas are grabbed,
+/// in which case neither can be moved.
+pub(crate) fn mass_shares(bola_one: &Bola, bola_two: &Bola) -> Option<(f64, f64)> {
+    let inverse_mass_one = bola_one.inverse_mass();
+    let inverse_mass_two = bola_two.inverse_mass();
+    let inverse_mass = inverse_mass_one + inverse_mass_two;
+    if inverse_mass == 0. {
+        return None;
+    }
+
+    Some((
+        inverse_mass_one / inverse_mass,
+        inverse_mass_two / inverse_mass,
+    ))
+}
+
+/// Brings a position which has moved past either edge of the range from 0
+/// to `max` back inside of it, according to the boundary modes of the low
+/// and high edges. Reflecting edges bounce the bola back, along with its
+/// velocity along the axis, and a bola fast enough to cross the whole range
+/// is reflected off both edges as many times as it would have hit them
+/// during the step. Bounces lose energy according to the coefficient of
+/// restitution, which also shortens how far the bola travels back from the
+/// edge.
+fn bounce_off_edges(
+    mut position: f64,
+    mut velocity: f64,
+    max: f64,
+    (low, high): (BoundaryMode, BoundaryMode),
+    restitution: f64,
+) -> EdgeOutcome {
+    // The canvas dimensions aren't known until the first client reports
+    // them, until then bolas are only stopped once they've drifted far away
+    if max <= 0. {
+        if position.abs() > MAX_COORDINATE {
+            return EdgeOutcome::Inside(position.clamp(-MAX_COORDINATE, MAX_COORDINATE), 0.);
+        }
+
+        return EdgeOutcome::Inside(position, velocity);
+    }
+
+    for _ in 0..MAX_BOUNCES_PER_STEP {
+        let (mode, edge) = if position < 0. {
+            (low, 0.)
+        } else if position > max {
+            (high, max)
+        } else {
+            break;
+        };
 
-        self.center.x = new_center_x;
-        self.center.y = new_center_y;
+        match mode {
+            BoundaryMode::Reflect => {
+                position = edge - (position - edge) * restitution;
+                velocity = -velocity * restitution;
+            }
+            BoundaryMode::Wrap => position = position.rem_euclid(max),
+            BoundaryMode::Open => return EdgeOutcome::Left,
+            BoundaryMode::Sticky => return EdgeOutcome::Stuck(edge),
+        }
     }
+
+    EdgeOutcome::Inside(position.clamp(0., max), velocity)
 }
 
-#[derive(Serialize)]
 pub(crate) struct BolasArena {
     bolas: Vec<Bola>,
 
-    #[serde(skip_serializing)]
-    velocity_scaling_factor: i32,
+    /// Ropes, springs and joints tying bolas together or to fixed points,
+    /// sent to clients so they can draw them
+    constraints: Vec<Constraint>,
 
-    #[serde(skip_serializing)]
-    refresh_rate: Duration,
+    /// Index in `bolas` of the bola with each id
+    bola_indices: HashMap<BolaId, usize>,
 
-    #[serde(skip_serializing)]
-    canvas_height: i32,
+    /// Id given to the next bola added to the arena
+    next_bola_id: BolaId,
 
-    #[serde(skip_serializing)]
-    canvas_width: i32,
+    /// Number of timesteps the arena has been simulated for
+    tick: u64,
 
-    #[serde(skip_serializing)]
-    last_collisions: HashSet<Collision>,
+    /// Static segments, rectangles and pegs bolas bounce off, sent to
+    /// clients so they can draw them
+    obstacles: Vec<Obstacle>,
 
-    #[serde(skip_serializing)]
-    id: Uuid,
+    /// Length of each step of the physics simulation
+    timestep: Duration,
+
+    /// Most substeps a timestep may be split into to keep fast bolas from
+    /// passing through each other
+    max_substeps: u32,
 
-    #[serde(skip_serializing)]
-    collision_detection_algorithm: CollisionDetectionAlgorithm,
+    integrator: Integrator,
+    force_fields: ForceFields,
+    materials: Materials,
+    boundaries: Boundaries,
+    canvas_height: i32,
+    canvas_width: i32,
+    id: Uuid,
+    broad_phase: Box<dyn BroadPhase>,
+    parallel_collision_detection: bool,
 }
 
 impl Drop for BolasArena {
@@ -90,23 +498,40 @@ impl Drop for BolasArena {
 }
 
 impl BolasArena {
-    pub(crate) fn new(
-        refresh_rate_ms: u64,
-        velocity_scaling_factor: i32,
-        collision_detection_algorithm: CollisionDetectionAlgorithm,
-    ) -> Self {
+    pub(crate) fn new(id: Uuid, config: &BolasConfig) -> Self {
         metrics::arenas_active().inc();
         metrics::arenas_total().inc();
 
+        let obstacles: Vec<_> = config
+            .arena_layout
+            .obstacles()
+            .filter_map(Obstacle::normalized)
+            .take(MAX_OBSTACLES)
+            .collect();
+        let mut broad_phase = new_broad_phase(
+            config.collision_detection_algorithm,
+            config.verified_collision_detection_algorithm,
+        );
+        broad_phase.index_obstacles(&obstacles);
+
         Self {
             bolas: Default::default(),
-            refresh_rate: Duration::from_millis(refresh_rate_ms),
+            constraints: Default::default(),
+            bola_indices: Default::default(),
+            next_bola_id: 0,
+            tick: 0,
+            obstacles,
+            timestep: Duration::from_millis(config.physics_timestep_ms),
+            max_substeps: config.max_physics_substeps,
+            integrator: config.integrator,
+            force_fields: config.force_fields.clone().normalized(),
+            materials: config.materials.normalized(),
+            boundaries: config.boundaries,
             canvas_height: 0,
             canvas_width: 0,
-            last_collisions: Default::default(),
-            velocity_scaling_factor,
-            id: Uuid::new_v4(),
-            collision_detection_algorithm,
+            id,
+            broad_phase,
+            parallel_collision_detection: config.parallel_collision_detection,
         }
     }
 
@@ -114,13 +539,219 @@ impl BolasArena {
         self.id
     }
 
-    pub(crate) fn add_bola(&mut self, mut bola: Bola) {
+    pub(crate) fn snapshot(&self) -> ArenaSnapshot {
+        ArenaSnapshot {
+            id: self.id,
+            tick: self.tick,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            bolas: self.bolas.clone(),
+            links: self.constraints.clone(),
+            obstacles: self.obstacles.clone(),
+            physics: self.physics(),
+        }
+    }
+
+    pub(crate) fn physics(&self) -> PhysicsParams {
+        PhysicsParams {
+            timestep_ms: self.timestep.as_millis() as u64,
+            integrator: self.integrator,
+            force_fields: self.force_fields.clone(),
+            materials: self.materials,
+            boundaries: self.boundaries,
+        }
+    }
+
+    /// Adds a bola to the arena, returning the id it was given. Fails,
+    /// leaving the arena unchanged, if the arena is already full.
+    pub(crate) fn add_bola(&mut self, mut bola: Bola) -> Result<BolaId, ErrorCode> {
+        if self.bolas.len() >= MAX_BOLAS {
+            return Err(ErrorCode::LimitReached);
+        }
+
         metrics::bolas_active().inc();
         metrics::bolas_total().inc();
 
-        bola.velocity.vel_x /= self.velocity_scaling_factor as f64;
-        bola.velocity.vel_y /= self.velocity_scaling_factor as f64;
+        bola.velocity.vel_x /= VELOCITY_SCALING_FACTOR;
+        bola.velocity.vel_y /= VELOCITY_SCALING_FACTOR;
+        bola.normalize();
+        bola.id = self.next_bola_id;
+        self.next_bola_id += 1;
+
+        self.bola_indices.insert(bola.id, self.bolas.len());
         self.bolas.push(bola);
+        self.broad_phase.insert(&self.bolas, self.bolas.len() - 1);
+
+        Ok(self.next_bola_id - 1)
+    }
+
+    /// Adds a chain of bolas, each tied to the next by a rope of the given
+    /// length. If no length is given, each rope is as long as the distance
+    /// between the bolas it ties, so the chain starts out taut. Fails,
+    /// leaving the arena unchanged, if the arena can't fit the ropes.
+    pub(crate) fn add_tethered_bolas(
+        &mut self,
+        bolas: Vec<Bola>,
+        length: Option<f64>,
+    ) -> Result<(), ErrorCode> {
+        if self.bolas.len() + bolas.len() > MAX_BOLAS
+            || self.constraints.len() + bolas.len().saturating_sub(1) > MAX_CONSTRAINTS
+        {
+            return Err(ErrorCode::LimitReached);
+        }
+
+        let first = self.bolas.len();
+        let ids = bolas
+            .into_iter()
+            .map(|b| self.add_bola(b))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (one_idx, pair) in (first..).zip(ids.windows(2)) {
+            let length = self.pair_constraint_length(one_idx, one_idx + 1, length);
+            self.constraints.push(Constraint::Rope {
+                one: pair[0],
+                two: pair[1],
+                length,
+            });
+        }
+        Ok(())
+    }
+
+    /// Adds a spring between a pair of bolas, with a rest length of the
+    /// distance between them if no length is given. Fails, leaving the arena
+    /// unchanged, if either bola doesn't exist or the arena is already full.
+    pub(crate) fn add_spring(
+        &mut self,
+        one: BolaId,
+        two: BolaId,
+        length: Option<f64>,
+        stiffness: f64,
+        damping: f64,
+    ) -> Result<(), ErrorCode> {
+        if self.constraints.len() >= MAX_CONSTRAINTS {
+            return Err(ErrorCode::LimitReached);
+        }
+
+        let (one_idx, two_idx) = self.pair_indices(one, two)?;
+
+        self.constraints.push(Constraint::Spring {
+            one,
+            two,
+            length: self.pair_constraint_length(one_idx, two_idx, length),
+            stiffness: normalize_coefficient(stiffness, 0., MAX_SPRING_STIFFNESS, 0.),
+            damping: normalize_coefficient(damping, 0., MAX_SPRING_DAMPING, 0.),
+        });
+        Ok(())
+    }
+
+    /// Adds a rigid joint between a pair of bolas, holding them the given
+    /// distance apart, or the distance between them if no length is given.
+    /// Fails, leaving the arena unchanged, if either bola doesn't exist or
+    /// the arena is already full.
+    pub(crate) fn add_distance_joint(
+        &mut self,
+        one: BolaId,
+        two: BolaId,
+        length: Option<f64>,
+    ) -> Result<(), ErrorCode> {
+        if self.constraints.len() >= MAX_CONSTRAINTS {
+            return Err(ErrorCode::LimitReached);
+        }
+
+        let (one_idx, two_idx) = self.pair_indices(one, two)?;
+
+        self.constraints.push(Constraint::DistanceJoint {
+            one,
+            two,
+            length: self.pair_constraint_length(one_idx, two_idx, length),
+        });
+        Ok(())
+    }
+
+    /// Pins a bola to a fixed point, at the given distance from it or the
+    /// distance between them if no length is given. A bola pinned without an
+    /// anchor is held in place where it is. Fails, leaving the arena
+    /// unchanged, if the bola doesn't exist, the anchor isn't a finite point
+    /// or the arena is already full.
+    pub(crate) fn add_pin_joint(
+        &mut self,
+        bola: BolaId,
+        anchor: Option<Point>,
+        length: Option<f64>,
+    ) -> Result<(), ErrorCode> {
+        if self.constraints.len() >= MAX_CONSTRAINTS {
+            return Err(ErrorCode::LimitReached);
+        }
+
+        if anchor.is_some_and(|a| !a.x.is_finite() || !a.y.is_finite()) {
+            return Err(ErrorCode::InvalidArgument);
+        }
+
+        let &bola_idx = self.bola_indices.get(&bola).ok_or(ErrorCode::UnknownBola)?;
+
+        let b = &self.bolas[bola_idx];
+
+        let anchor = anchor.unwrap_or(Point {
+            x: b.center.x,
+            y: b.center.y,
+        });
+        let distance = ((b.center.x - anchor.x).powf(2.) + (b.center.y - anchor.y).powf(2.)).sqrt();
+        let length = normalize_coefficient(
+            length.unwrap_or(distance),
+            0.,
+            MAX_CONSTRAINT_LENGTH,
+            distance.min(MAX_CONSTRAINT_LENGTH),
+        );
+
+        self.constraints.push(Constraint::PinJoint {
+            bola,
+            anchor,
+            length,
+        });
+        Ok(())
+    }
+
+    /// Indices of a pair of distinct bolas, failing if they're the same bola
+    /// or either doesn't exist
+    fn pair_indices(&self, one: BolaId, two: BolaId) -> Result<(usize, usize), ErrorCode> {
+        if one == two {
+            return Err(ErrorCode::InvalidArgument);
+        }
+
+        let index = |id| self.bola_indices.get(&id).ok_or(ErrorCode::UnknownBola);
+        Ok((*index(one)?, *index(two)?))
+    }
+
+    /// Length of a constraint between a pair of bolas, defaulting to the
+    /// distance between them. Constraints shorter than the bolas are wide
+    /// would keep them permanently overlapping, so they're lengthened.
+    fn pair_constraint_length(&self, one: usize, two: usize, length: Option<f64>) -> f64 {
+        let bola_one = &self.bolas[one];
+        let bola_two = &self.bolas[two];
+
+        let min_length = bola_one.radius + bola_two.radius;
+        let length = length.unwrap_or_else(|| {
+            ((bola_one.center.x - bola_two.center.x).powf(2.)
+                + (bola_one.center.y - bola_two.center.y).powf(2.))
+            .sqrt()
+        });
+
+        normalize_coefficient(length, min_length, MAX_CONSTRAINT_LENGTH, min_length)
+    }
+
+    /// Adds a static obstacle to the arena. Fails, leaving the arena
+    /// unchanged, if the obstacle isn't valid or the arena is already full.
+    pub(crate) fn add_obstacle(&mut self, obstacle: Obstacle) -> Result<(), ErrorCode> {
+        let obstacle = obstacle.normalized().ok_or(ErrorCode::InvalidArgument)?;
+        if self.obstacles.len() >= MAX_OBSTACLES {
+            return Err(ErrorCode::LimitReached);
+        }
+
+        self.obstacles.push(obstacle);
+        self.broad_phase.index_obstacles(&self.obstacles);
+        Ok(())
     }
 
     pub(crate) fn set_canvas_dimensions(&mut self, height: i32, width: i32) {
@@ -128,76 +759,874 @@ impl BolasArena {
         self.canvas_width = width;
     }
 
+    pub(crate) fn set_force_fields(&mut self, force_fields: ForceFields) {
+        self.force_fields = force_fields.normalized();
+    }
+
+    pub(crate) fn set_materials(&mut self, materials: Materials) {
+        self.materials = materials.normalized();
+    }
+
+    pub(crate) fn set_boundaries(&mut self, boundaries: Boundaries) {
+        self.boundaries = boundaries;
+    }
+
+    /// Removes the bola with the given id, along with any constraints
+    /// attached to it. Fails if there's no such bola.
+    pub(crate) fn delete_bola(&mut self, bola_id: BolaId) -> Result<(), ErrorCode> {
+        let &bola_idx = self
+            .bola_indices
+            .get(&bola_id)
+            .ok_or(ErrorCode::UnknownBola)?;
+
+        self.remove_bola(bola_idx);
+        Ok(())
+    }
+
+    /// Removes every bola from the arena, along with the constraints between
+    /// them, leaving the arena's obstacles in place
+    pub(crate) fn clear(&mut self) {
+        metrics::bolas_active().dec_by(self.bolas.len() as u64);
+
+        self.bolas.clear();
+        self.bola_indices.clear();
+        self.constraints.clear();
+        self.broad_phase.clear();
+    }
+
+    /// Grabs the bola with the given id on behalf of a client, holding it in
+    /// place until the client drags or releases it. Fails if there's no
+    /// such bola or another client is already holding it.
+    pub(crate) fn grab_bola(&mut self, bola_id: BolaId, client_id: Uuid) -> Result<(), ErrorCode> {
+        let bola = self.get_bola_mut(bola_id)?;
+        if bola.grab.as_ref().is_some_and(|g| g.client_id != client_id) {
+            return Err(ErrorCode::NotPermitted);
+        }
+
+        bola.grab = Some(Grab {
+            client_id,
+            target: bola.center,
+        });
+        Ok(())
+    }
+
+    /// Moves the bola with the given id towards where the client holding it
+    /// has dragged it to. Grabbed bolas ignore the edges of the arena, so
+    /// the target is kept inside the canvas. Fails if the client isn't
+    /// holding it or the target isn't a finite point.
+    pub(crate) fn drag_bola(
+        &mut self,
+        bola_id: BolaId,
+        client_id: Uuid,
+        target: Point,
+    ) -> Result<(), ErrorCode> {
+        if !target.x.is_finite() || !target.y.is_finite() {
+            return Err(ErrorCode::InvalidArgument);
+        }
+
+        let target = self.clamp_to_canvas(target);
+        self.get_grab_mut(bola_id, client_id)?.target = target;
+        Ok(())
+    }
+
+    /// Brings a point inside the canvas, or within `MAX_COORDINATE` of the
+    /// origin along each axis while the canvas dimensions aren't known yet
+    fn clamp_to_canvas(&self, point: Point) -> Point {
+        let clamp = |coordinate: f64, max: i32| {
+            if max > 0 {
+                coordinate.clamp(0., max as f64)
+            } else {
+                coordinate.clamp(-MAX_COORDINATE, MAX_COORDINATE)
+            }
+        };
+
+        Point {
+            x: clamp(point.x, self.canvas_width),
+            y: clamp(point.y, self.canvas_height),
+        }
+    }
+
+    /// Lets go of the bola with the given id, throwing it with the given
+    /// velocity in pixels per second. Fails if the client isn't holding it.
+    pub(crate) fn release_bola(
+        &mut self,
+        bola_id: BolaId,
+        client_id: Uuid,
+        velocity: Vector,
+    ) -> Result<(), ErrorCode> {
+        self.get_grab_mut(bola_id, client_id)?;
+
+        let bola = self.get_bola_mut(bola_id)?;
+        bola.grab = None;
+        bola.velocity = Vector {
+            vel_x: normalize_coefficient(velocity.vel_x, -MAX_THROW_SPEED, MAX_THROW_SPEED, 0.),
+            vel_y: normalize_coefficient(velocity.vel_y, -MAX_THROW_SPEED, MAX_THROW_SPEED, 0.),
+        };
+        Ok(())
+    }
+
+    /// Drops every bola held by the given client, leaving them where they
+    /// are, called when the client disconnects
+    pub(crate) fn release_grabs(&mut self, client_id: Uuid) {
+        for b in &mut self.bolas {
+            if b.grab.as_ref().is_some_and(|g| g.client_id == client_id) {
+                b.grab = None;
+                b.velocity = Vector {
+                    vel_x: 0.,
+                    vel_y: 0.,
+                };
+            }
+        }
+    }
+
+    fn get_bola_mut(&mut self, bola_id: BolaId) -> Result<&mut Bola, ErrorCode> {
+        let bola_idx = *self
+            .bola_indices
+            .get(&bola_id)
+            .ok_or(ErrorCode::UnknownBola)?;
+        Ok(&mut self.bolas[bola_idx])
+    }
+
+    /// Grab the given client has on a bola, failing if there's no such bola
+    /// or the client isn't holding it
+    fn get_grab_mut(&mut self, bola_id: BolaId, client_id: Uuid) -> Result<&mut Grab, ErrorCode> {
+        self.get_bola_mut(bola_id)?
+            .grab
+            .as_mut()
+            .filter(|g| g.client_id == client_id)
+            .ok_or(ErrorCode::NotPermitted)
+    }
+
+    /// Removes the bola at the given index, along with any constraints
+    /// attached to it
+    fn remove_bola(&mut self, bola_idx: usize) {
+        metrics::bolas_active().dec();
+
+        let bola = self.bolas.remove(bola_idx);
+        self.bola_indices.remove(&bola.id);
+        self.broad_phase.remove(&self.bolas, bola_idx);
+        self.constraints.retain(|c| !c.is_attached_to(bola.id));
+
+        // The bolas after it have moved down an index
+        for (idx, b) in self.bolas.iter().enumerate().skip(bola_idx) {
+            self.bola_indices.insert(b.id, idx);
+        }
+    }
+
+    /// Advances the simulation by a single timestep
     pub(crate) fn update_state(&mut self) {
+        let dt = self.timestep.as_secs_f64();
+
+        // Set before picking the number of substeps, so that a bola dragged
+        // quickly gets enough substeps not to pass through others
         for b in &mut self.bolas {
-            b.update_position(self.canvas_height as f64, self.canvas_width as f64);
+            b.follow_grab(dt);
         }
 
-        self.update_for_collisions();
+        let substeps = self.get_substeps(dt);
+        let substep_dt = dt / substeps as f64;
+
+        for _ in 0..substeps {
+            for b in &mut self.bolas {
+                if b.grab.is_some() {
+                    b.advance(substep_dt);
+                    continue;
+                }
+
+                let materials = b.materials(&self.materials);
+                self.integrator.integrate(b, &self.force_fields, substep_dt);
+                b.apply_friction(&materials, substep_dt);
+            }
+
+            solve_constraints(
+                &self.constraints,
+                &mut self.bolas,
+                &self.bola_indices,
+                substep_dt,
+            );
+
+            let mut left = Vec::new();
+            for (bola_idx, b) in self.bolas.iter_mut().enumerate() {
+                if b.grab.is_some() {
+                    continue;
+                }
+
+                let materials = b.materials(&self.materials);
+                if !b.bounce_off_walls(
+                    self.canvas_height as f64,
+                    self.canvas_width as f64,
+                    &self.boundaries,
+                    materials.wall_restitution,
+                ) {
+                    left.push(bola_idx);
+                }
+            }
+
+            // Removed from the back so the indices of the bolas still to be
+            // removed aren't shifted
+            for bola_idx in left.into_iter().rev() {
+                log::debug!("Bola left the arena"; "arena" => %self.id, "bola" => ?self.bolas[bola_idx]);
+                self.remove_bola(bola_idx);
+            }
+
+            self.update_for_collisions(substep_dt);
+        }
+
+        self.tick += 1;
     }
 
-    fn update_for_collisions(&mut self) {
-        let mut collision_detector: CollisionDetector = self.collision_detection_algorithm.into();
-        let collisions = self
+    /// Collisions are only detected between substeps, so a bola moving
+    /// further than its own radius in a single substep could pass straight
+    /// through another. The timestep is split into enough substeps that no
+    /// bola does, up to the configured maximum, beyond which the paths of
+    /// the bolas that still do are checked instead.
+    fn get_substeps(&self, dt: f64) -> u32 {
+        let substeps = self
+            .bolas
+            .iter()
+            .map(|b| (b.speed() * dt / b.radius).ceil())
+            .fold(1., f64::max);
+
+        (substeps.min(self.max_substeps as f64) as u32).max(1)
+    }
+
+    fn update_for_collisions(&mut self, dt: f64) {
+        self.broad_phase.rebuild(&self.bolas);
+        if self.update_for_swept_collisions(dt) {
+            self.broad_phase.rebuild(&self.bolas);
+        }
+
+        let collisions = if self.parallel_collision_detection {
+            self.broad_phase.par_query_pairs(&self.bolas)
+        } else {
+            self.broad_phase.query_pairs(&self.bolas)
+        };
+
+        for c in &collisions {
+            self.resolve_collision(c, dt);
+            self.separate_bolas(c);
+        }
+
+        if self.obstacles.is_empty() {
+            return;
+        }
+
+        let contacts = if self.parallel_collision_detection {
+            self.broad_phase
+                .par_query_obstacles(&self.bolas, &self.obstacles)
+        } else {
+            self.broad_phase
+                .query_obstacles(&self.bolas, &self.obstacles)
+        };
+
+        for (bola_idx, obstacle_idx) in contacts {
+            let bola = &mut self.bolas[bola_idx];
+            if bola.grab.is_some() {
+                continue;
+            }
+
+            let restitution = bola.materials(&self.materials).wall_restitution;
+            bola.bounce_off_obstacle(&self.obstacles[obstacle_idx], restitution);
+        }
+    }
+
+    /// Bolas moving further than their own radius in a substep, which only
+    /// happens once the timestep has been split into as many substeps as
+    /// allowed, can pass through another bola or an obstacle without ever
+    /// overlapping it between substeps. The broad phase is queried for what
+    /// lies along their paths over the substep, and they're bounced off the
+    /// first obstacle and the first bola they touched. Returns whether there
+    /// were any such bolas, and so whether any bolas may have been moved.
+    fn update_for_swept_collisions(&mut self, dt: f64) -> bool {
+        let fast: Vec<_> = self
             .bolas
             .iter()
             .enumerate()
-            .flat_map(|(bola_one_idx, _)| {
-                collision_detector.detect_collisions_for_bola(&self.bolas, bola_one_idx)
-            })
+            .filter(|(_, b)| b.speed() * dt > b.radius)
+            .map(|(bola_idx, _)| bola_idx)
+            .collect();
+        if fast.is_empty() {
+            return false;
+        }
+
+        let obstacle_hits: Vec<_> = fast
+            .iter()
+            .filter_map(|&bola_idx| Some((bola_idx, self.first_obstacle_touched(bola_idx, dt)?)))
             .collect();
+        for (bola_idx, (center, obstacle_idx)) in obstacle_hits {
+            let bola = &mut self.bolas[bola_idx];
+            let restitution = bola.materials(&self.materials).wall_restitution;
+            bola.center = center;
+            bola.bounce_off_obstacle(&self.obstacles[obstacle_idx], restitution);
+        }
 
+        let collisions: Vec<_> = if self.parallel_collision_detection {
+            fast.par_iter()
+                .filter_map(|&bola_idx| self.first_bola_touched(bola_idx, &fast, dt))
+                .collect()
+        } else {
+            fast.iter()
+                .filter_map(|&bola_idx| self.first_bola_touched(bola_idx, &fast, dt))
+                .collect()
+        };
         for c in &collisions {
-            if self.last_collisions.contains(c) {
-                continue;
+            self.resolve_collision(c, dt);
+        }
+
+        true
+    }
+
+    /// Finds the first bola the fast bola at the given index touched over
+    /// the last `dt` seconds, if it passed into any. Other fast bolas may
+    /// have been anywhere along their own paths when they met, so they're
+    /// always checked, as well as any bolas the broad phase finds along its
+    /// path. Bolas overlapping at the end of the substep are left to the
+    /// broad phase.
+    fn first_bola_touched(
+        &self,
+        bola_one_idx: usize,
+        fast: &[usize],
+        dt: f64,
+    ) -> Option<Collision> {
+        let bola_one = &self.bolas[bola_one_idx];
+        let candidates = self
+            .broad_phase
+            .query_region(&self.bolas, &bola_one.swept_bounds(dt));
+
+        candidates
+            .into_iter()
+            .chain(fast.iter().copied())
+            .filter(|bola_two_idx| {
+                *bola_two_idx != bola_one_idx
+                    && !bolas_collide(bola_one, &self.bolas[*bola_two_idx])
+            })
+            .map(|bola_two_idx| {
+                let time = time_since_impact(bola_one, &self.bolas[bola_two_idx], dt);
+                (bola_two_idx, time)
+            })
+            .filter(|(_, time)| *time > 0.)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(bola_two_idx, _)| Collision::new(bola_one_idx, bola_two_idx))
+    }
+
+    /// Steps the fast bola at the given index along its path over the last
+    /// `dt` seconds, in steps short enough that it can't skip over an
+    /// obstacle, returning where it first touched an obstacle it wasn't
+    /// already touching, along with the obstacle
+    fn first_obstacle_touched(&self, bola_idx: usize, dt: f64) -> Option<(Point, usize)> {
+        let bola = &self.bolas[bola_idx];
+        if bola.grab.is_some() {
+            return None;
+        }
+
+        let mut path = bola.clone();
+        path.advance(-dt);
+
+        let candidates: Vec<_> = self
+            .broad_phase
+            .query_obstacles_in_region(&self.obstacles, &bola.swept_bounds(dt))
+            .into_iter()
+            .filter(|obstacle_idx| self.obstacles[*obstacle_idx].contact(&path).is_none())
+            .collect();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        let steps = (bola.speed() * dt / (bola.radius / 2.)).ceil();
+        for _ in 0..steps as usize {
+            path.advance(dt / steps);
+            if let Some(&obstacle_idx) = candidates
+                .iter()
+                .find(|obstacle_idx| self.obstacles[**obstacle_idx].contact(&path).is_some())
+            {
+                return Some((path.center, obstacle_idx));
             }
+        }
 
-            let bola_one = &self.bolas[c.one];
-            let bola_two = &self.bolas[c.two];
+        None
+    }
 
-            let distance = ((bola_one.center.x - bola_two.center.x).powf(2.)
-                + (bola_one.center.y - bola_two.center.y).powf(2.))
+    /// Exchanges momentum between a pair of colliding bolas
+    fn resolve_collision(&mut self, c: &Collision, dt: f64) {
+        let bola_one = &self.bolas[c.one];
+        let bola_two = &self.bolas[c.two];
+
+        // By the time the collision is detected the bolas may have moved
+        // well into each other, so the collision is resolved from where
+        // they were at the moment of impact
+        let rewind = time_since_impact(bola_one, bola_two, dt);
+        let collision_vector = (
+            (bola_one.center.x - bola_one.velocity.vel_x * rewind)
+                - (bola_two.center.x - bola_two.velocity.vel_x * rewind),
+            (bola_one.center.y - bola_one.velocity.vel_y * rewind)
+                - (bola_two.center.y - bola_two.velocity.vel_y * rewind),
+        );
+
+        let distance = (collision_vector.0.powf(2.) + collision_vector.1.powf(2.)).sqrt();
+        if distance == 0. {
+            return;
+        }
+
+        let collision_vector_normalized =
+            (collision_vector.0 / distance, collision_vector.1 / distance);
+        let relative_velocity_vector = (
+            (bola_one.velocity.vel_x - bola_two.velocity.vel_x),
+            (bola_one.velocity.vel_y - bola_two.velocity.vel_y),
+        );
+        let speed = relative_velocity_vector.0 * collision_vector_normalized.0
+            + relative_velocity_vector.1 * collision_vector_normalized.1;
+
+        // Bolas already moving apart are left alone, otherwise an
+        // inelastic collision would pull them back together
+        if speed >= 0. {
+            return;
+        }
+
+        // Each bola's change in velocity along the collision normal is
+        // proportional to the other's share of the total mass, which
+        // for a perfectly elastic collision between bolas of equal mass
+        // reduces to swapping their normal velocities. The coefficients
+        // of restitution of the two bolas are combined with their
+        // geometric mean.
+        let restitution = (bola_one.materials(&self.materials).restitution
+            * bola_two.materials(&self.materials).restitution)
             .sqrt();
+        let Some((share_one, share_two)) = mass_shares(bola_one, bola_two) else {
+            return;
+        };
+        let speed_one = (1. + restitution) * share_one * speed;
+        let speed_two = (1. + restitution) * share_two * speed;
 
-            if distance == 0. {
-                continue;
+        // Rewind both bolas to the point of impact, then move them on
+        // for the same time with their velocities after the collision
+        let bola_one = &mut self.bolas[c.one];
+        bola_one.advance(-rewind);
+        bola_one.velocity.vel_x -= collision_vector_normalized.0 * speed_one;
+        bola_one.velocity.vel_y -= collision_vector_normalized.1 * speed_one;
+        bola_one.advance(rewind);
+
+        let bola_two = &mut self.bolas[c.two];
+        bola_two.advance(-rewind);
+        bola_two.velocity.vel_x += collision_vector_normalized.0 * speed_two;
+        bola_two.velocity.vel_y += collision_vector_normalized.1 * speed_two;
+        bola_two.advance(rewind);
+
+        let bola_one = &self.bolas[c.one];
+        let bola_two = &self.bolas[c.two];
+        log::debug!(
+            "Updated for collision between bolas";
+            "arena" => %self.id,
+            "bola_one" => ?bola_one,
+            "bola_two" => ?bola_two,
+        );
+    }
+
+    /// Pushes a pair of overlapping bolas apart along the line between their
+    /// centers, moving each by an amount proportional to the other's share
+    /// of their total mass, so a light bola is pushed out of a heavy one
+    fn separate_bolas(&mut self, c: &Collision) {
+        let bola_one = &self.bolas[c.one];
+        let bola_two = &self.bolas[c.two];
+
+        let offset = (
+            bola_one.center.x - bola_two.center.x,
+            bola_one.center.y - bola_two.center.y,
+        );
+        let distance = (offset.0.powf(2.) + offset.1.powf(2.)).sqrt();
+        let penetration = bola_one.radius + bola_two.radius - distance;
+        if penetration <= PENETRATION_SLOP {
+            return;
+        }
+
+        // Bolas spawned exactly on top of each other have no line between
+        // their centers, so they're pushed apart horizontally
+        let normal = if distance == 0. {
+            (1., 0.)
+        } else {
+            (offset.0 / distance, offset.1 / distance)
+        };
+
+        let correction = (penetration - PENETRATION_SLOP) * PENETRATION_CORRECTION;
+        let Some((share_one, share_two)) = mass_shares(bola_one, bola_two) else {
+            return;
+        };
+        let correction_one = correction * share_one;
+        let correction_two = correction * share_two;
+
+        let bola_one = &mut self.bolas[c.one];
+        bola_one.center.x += normal.0 * correction_one;
+        bola_one.center.y += normal.1 * correction_one;
+
+        let bola_two = &mut self.bolas[c.two];
+        bola_two.center.x -= normal.0 * correction_two;
+        bola_two.center.y -= normal.1 * correction_two;
+    }
+
+    pub(crate) fn get_timestep(&self) -> Duration {
+        self.timestep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::obstacles::Segment;
+    use crate::settings::{BolasConfig, BolasSettings};
+    use std::convert::TryFrom;
+
+    fn arena() -> BolasArena {
+        let config = BolasConfig::try_from(&BolasSettings::default()).unwrap();
+        let mut arena = BolasArena::new(Uuid::nil(), &config);
+        arena.set_canvas_dimensions(1000, 1000);
+        arena
+    }
+
+    fn assert_finite(bola: &Bola) {
+        assert!(
+            [
+                bola.center.x,
+                bola.center.y,
+                bola.velocity.vel_x,
+                bola.velocity.vel_y,
+                bola.inverse_mass(),
+            ]
+            .iter()
+            .all(|v| v.is_finite()),
+            "{:?}",
+            bola
+        );
+    }
+
+    #[test]
+    fn new_bolas_and_drags_stay_finite() {
+        let mut arena = arena();
+        arena
+            .add_bola(Bola {
+                center: Point {
+                    x: f64::NAN,
+                    y: f64::INFINITY,
+                },
+                velocity: Vector {
+                    vel_x: 1e308,
+                    vel_y: f64::NEG_INFINITY,
+                },
+                mass: 1e-310,
+                ..Bola::at(0., 0., 20.)
+            })
+            .unwrap();
+        assert_finite(&arena.bolas[0]);
+        assert_eq!(arena.bolas[0].mass, MIN_BOLA_MASS);
+        arena.add_bola(Bola::at(500., 500., 20.)).unwrap();
+
+        let client_id = Uuid::new_v4();
+        assert!(arena.grab_bola(1, client_id).is_ok());
+        let nowhere = Point { x: f64::NAN, y: 0. };
+        assert_eq!(
+            arena.drag_bola(1, client_id, nowhere),
+            Err(ErrorCode::InvalidArgument)
+        );
+
+        for _ in 0..10 {
+            arena.update_state();
+            arena.bolas.iter().for_each(assert_finite);
+        }
+    }
+
+    #[test]
+    fn new_bolas_and_drags_stay_near_the_canvas() {
+        let mut arena = arena();
+        arena.add_bola(Bola::at(1e15, -1e15, 20.)).unwrap();
+        assert_eq!(
+            arena.bolas[0].center,
+            Point {
+                x: MAX_COORDINATE,
+                y: -MAX_COORDINATE
             }
+        );
 
-            let collision_vector = (
-                (bola_one.center.x - bola_two.center.x),
-                (bola_one.center.y - bola_two.center.y),
-            );
-            let collision_vector_normalized =
-                (collision_vector.0 / distance, collision_vector.1 / distance);
-            let relative_velocity_vector = (
-                (bola_one.velocity.vel_x - bola_two.velocity.vel_x),
-                (bola_one.velocity.vel_y - bola_two.velocity.vel_y),
-            );
-            let speed = relative_velocity_vector.0 * collision_vector_normalized.0
-                + relative_velocity_vector.1 * collision_vector_normalized.1;
+        let bola = arena.add_bola(Bola::at(500., 500., 20.)).unwrap();
+        let client_id = Uuid::new_v4();
+        assert!(arena.grab_bola(bola, client_id).is_ok());
+        assert!(arena
+            .drag_bola(bola, client_id, Point { x: 1e15, y: -5. })
+            .is_ok());
+        arena.update_state();
+        assert_eq!(arena.bolas[1].center, Point { x: 1000., y: 0. });
+    }
+
+    #[test]
+    fn bolas_stay_near_the_origin_before_the_canvas_is_known() {
+        let config = BolasConfig::try_from(&BolasSettings::default()).unwrap();
+        let mut arena = BolasArena::new(Uuid::nil(), &config);
+        arena
+            .add_bola(Bola {
+                velocity: Vector {
+                    vel_x: MAX_THROW_SPEED * VELOCITY_SCALING_FACTOR,
+                    vel_y: -MAX_THROW_SPEED * VELOCITY_SCALING_FACTOR,
+                },
+                ..Bola::at(0., 0., 20.)
+            })
+            .unwrap();
+
+        let steps = 2. * MAX_COORDINATE / MAX_THROW_SPEED / arena.timestep.as_secs_f64();
+        for _ in 0..steps as usize {
+            arena.update_state();
+        }
+
+        let bola = &arena.bolas[0];
+        assert_eq!(
+            bola.center,
+            Point {
+                x: MAX_COORDINATE,
+                y: -MAX_COORDINATE
+            }
+        );
+        assert_eq!((bola.velocity.vel_x, bola.velocity.vel_y), (0., 0.));
+    }
+
+    #[test]
+    fn bolas_are_capped() {
+        let mut arena = arena();
+        for _ in 0..MAX_BOLAS - 1 {
+            arena.add_bola(Bola::at(500., 500., 2.)).unwrap();
+        }
+
+        assert_eq!(
+            arena.add_tethered_bolas(
+                vec![Bola::at(300., 100., 20.), Bola::at(400., 100., 20.)],
+                None
+            ),
+            Err(ErrorCode::LimitReached)
+        );
+        assert!(arena.add_bola(Bola::at(500., 500., 2.)).is_ok());
+        assert_eq!(
+            arena.add_bola(Bola::at(500., 500., 2.)),
+            Err(ErrorCode::LimitReached)
+        );
+        assert_eq!(arena.bolas.len(), MAX_BOLAS);
+        assert!(arena.constraints.is_empty());
+    }
+
+    #[test]
+    fn constraints_are_capped() {
+        let mut arena = arena();
+        let one = arena.add_bola(Bola::at(100., 100., 20.)).unwrap();
+        let two = arena.add_bola(Bola::at(200., 100., 20.)).unwrap();
+
+        for _ in 0..MAX_CONSTRAINTS {
+            assert!(arena.add_spring(one, two, None, 10., 0.).is_ok());
+        }
+
+        let full = Err(ErrorCode::LimitReached);
+        assert_eq!(arena.add_spring(one, two, None, 10., 0.), full);
+        assert_eq!(arena.add_distance_joint(one, two, None), full);
+        assert_eq!(arena.add_pin_joint(one, None, None), full);
+        assert_eq!(
+            arena.add_tethered_bolas(
+                vec![Bola::at(300., 100., 20.), Bola::at(400., 100., 20.)],
+                None
+            ),
+            full
+        );
+        assert_eq!(arena.constraints.len(), MAX_CONSTRAINTS);
+        assert_eq!(arena.bolas.len(), 2);
+    }
+
+    #[test]
+    fn grabs_are_held_by_a_single_client() {
+        let mut arena = arena();
+        let bola = arena.add_bola(Bola::at(100., 100., 20.)).unwrap();
+        let (holder, other) = (Uuid::new_v4(), Uuid::new_v4());
+        let here = Point { x: 200., y: 100. };
+
+        assert!(arena.grab_bola(bola, holder).is_ok());
+        assert_eq!(arena.grab_bola(bola, other), Err(ErrorCode::NotPermitted));
+        assert_eq!(
+            arena.drag_bola(bola, other, here),
+            Err(ErrorCode::NotPermitted)
+        );
+        assert_eq!(
+            arena.drag_bola(bola + 1, holder, here),
+            Err(ErrorCode::UnknownBola)
+        );
+        assert!(arena.drag_bola(bola, holder, here).is_ok());
+    }
+
+    #[test]
+    fn pin_joints_need_a_finite_anchor() {
+        let mut arena = arena();
+        let bola = arena.add_bola(Bola::at(100., 100., 20.)).unwrap();
+        let anchor = Point {
+            x: f64::INFINITY,
+            y: 0.,
+        };
+
+        assert_eq!(
+            arena.add_pin_joint(bola, Some(anchor), None),
+            Err(ErrorCode::InvalidArgument)
+        );
+        assert!(arena.constraints.is_empty());
+    }
+
+    #[test]
+    fn fast_bolas_dont_pass_through_others() {
+        let mut arena = arena();
+
+        // Moves 10px a substep even at the most substeps, more than twice
+        // the distance at which it touches the target
+        arena
+            .add_bola(Bola {
+                velocity: Vector {
+                    vel_x: 5000. * VELOCITY_SCALING_FACTOR,
+                    vel_y: 0.,
+                },
+                ..Bola::at(100., 500., 2.)
+            })
+            .unwrap();
+        arena.add_bola(Bola::at(305., 500., 2.)).unwrap();
+
+        for _ in 0..5 {
+            arena.update_state();
+        }
+
+        let (bullet, target) = (&arena.bolas[0], &arena.bolas[1]);
+        assert!(target.velocity.vel_x > 0., "{:?}", target);
+        assert!(bullet.center.x < target.center.x, "{:?}", bullet);
+    }
+
+    #[test]
+    fn fast_bolas_dont_pass_through_obstacles() {
+        let mut arena = arena();
+        arena
+            .add_obstacle(Obstacle::Segment(Segment {
+                x1: 305.,
+                y1: 400.,
+                x2: 305.,
+                y2: 600.,
+            }))
+            .unwrap();
+        arena
+            .add_bola(Bola {
+                velocity: Vector {
+                    vel_x: 5000. * VELOCITY_SCALING_FACTOR,
+                    vel_y: 0.,
+                },
+                ..Bola::at(100., 500., 2.)
+            })
+            .unwrap();
 
-            let bola_one = &mut self.bolas[c.one];
-            bola_one.velocity.vel_x -= collision_vector_normalized.0 * speed;
-            bola_one.velocity.vel_y -= collision_vector_normalized.1 * speed;
+        for _ in 0..5 {
+            arena.update_state();
+        }
 
-            let bola_two = &mut self.bolas[c.two];
-            bola_two.velocity.vel_x += collision_vector_normalized.0 * speed;
-            bola_two.velocity.vel_y += collision_vector_normalized.1 * speed;
+        let bullet = &arena.bolas[0];
+        assert!(bullet.velocity.vel_x < 0., "{:?}", bullet);
+        assert!(bullet.center.x < 305., "{:?}", bullet);
+    }
 
-            let bola_one = &self.bolas[c.one];
-            let bola_two = &self.bolas[c.two];
-            log::debug!(
-                "Updated for collision between bolas";
-                "arena" => %self.id,
-                "bola_one" => ?bola_one,
-                "bola_two" => ?bola_two,
-            )
+    fn boundaries(mode: BoundaryMode) -> Boundaries {
+        Boundaries {
+            top: mode,
+            bottom: mode,
+            left: mode,
+            right: mode,
         }
+    }
 
-        self.last_collisions = collisions;
+    /// Bola heading right at the given speed in pixels per second
+    fn bola_heading_right(x: f64, speed: f64) -> Bola {
+        Bola {
+            velocity: Vector {
+                vel_x: speed * VELOCITY_SCALING_FACTOR,
+                vel_y: 0.,
+            },
+            ..Bola::at(x, 500., 5.)
+        }
     }
 
-    pub(crate) fn get_refresh_rate(&self) -> Duration {
-        self.refresh_rate
+    #[test]
+    fn wrapping_bolas_reappear_on_the_opposite_edge() {
+        let mut arena = arena();
+        arena.set_boundaries(boundaries(BoundaryMode::Wrap));
+        arena.add_bola(bola_heading_right(995., 1000.)).unwrap();
+
+        arena.update_state();
+
+        let bola = &arena.bolas[0];
+        assert!(bola.center.x < 50., "{:?}", bola);
+        assert_eq!(bola.velocity.vel_x, 1000., "{:?}", bola);
+    }
+
+    #[test]
+    fn bolas_leaving_through_open_edges_are_removed() {
+        let mut arena = arena();
+        arena.set_boundaries(Boundaries {
+            right: BoundaryMode::Open,
+            ..boundaries(BoundaryMode::Reflect)
+        });
+        let leaving = arena.add_bola(bola_heading_right(995., 1000.)).unwrap();
+        let staying = arena.add_bola(Bola::at(500., 500., 5.)).unwrap();
+        arena.add_spring(leaving, staying, None, 0., 0.).unwrap();
+
+        arena.update_state();
+
+        assert_eq!(arena.bolas.len(), 1);
+        assert_eq!(arena.bolas[0].id, staying);
+        assert!(arena.constraints.is_empty());
+        assert!(!arena.bola_indices.contains_key(&leaving));
+        assert_eq!(arena.bola_indices[&staying], 0);
+        assert!(arena.delete_bola(staying).is_ok());
+    }
+
+    #[test]
+    fn bolas_stop_dead_against_sticky_edges() {
+        let mut arena = arena();
+        arena.set_boundaries(boundaries(BoundaryMode::Sticky));
+        arena
+            .add_bola(Bola {
+                velocity: Vector {
+                    vel_x: 1000. * VELOCITY_SCALING_FACTOR,
+                    vel_y: 300. * VELOCITY_SCALING_FACTOR,
+                },
+                ..Bola::at(995., 500., 5.)
+            })
+            .unwrap();
+
+        arena.update_state();
+
+        let bola = &arena.bolas[0];
+        assert_eq!(bola.center.x, 1000., "{:?}", bola);
+        assert_eq!((bola.velocity.vel_x, bola.velocity.vel_y), (0., 0.));
+    }
+
+    #[test]
+    fn fast_bolas_bounce_back_and_forth_within_a_step() {
+        // Crosses a 10px wide range nearly three times, bouncing off the far
+        // edge, the near edge and the far edge again
+        let reflect = (BoundaryMode::Reflect, BoundaryMode::Reflect);
+        let EdgeOutcome::Inside(position, velocity) = bounce_off_edges(35., 100., 10., reflect, 1.)
+        else {
+            panic!("bola didn't stay inside");
+        };
+        assert_eq!((position, velocity), (5., -100.));
+
+        let mut arena = arena();
+        arena.set_canvas_dimensions(10, 10);
+        arena
+            .add_bola(Bola {
+                velocity: Vector {
+                    vel_x: MAX_THROW_SPEED * VELOCITY_SCALING_FACTOR,
+                    vel_y: -MAX_THROW_SPEED * VELOCITY_SCALING_FACTOR,
+                },
+                ..Bola::at(5., 5., 2.)
+            })
+            .unwrap();
+
+        for _ in 0..5 {
+            arena.update_state();
+            let bola = &arena.bolas[0];
+            assert!((0. ..=10.).contains(&bola.center.x), "{:?}", bola);
+            assert!((0. ..=10.).contains(&bola.center.y), "{:?}", bola);
+        }
     }
 }
@@ -1,113 +1,381 @@
 use crate::bolas::Bola;
-use bio::data_structures::interval_tree::IntervalTree;
+use crate::obstacles::Obstacle;
+use crate::quadtree::Aabb;
 use foundations::settings::settings;
+use rayon::prelude::*;
 use std::collections::HashSet;
-use std::ops::Range;
 
-pub(crate) const BOLA_COLLISION_RADIUS: i32 = 20;
+mod distance;
+mod interval_trees;
+mod obstacles;
+mod quadtree;
+mod spatial_hash;
+mod sweep_and_prune;
+mod verify;
 
-#[derive(Eq, Hash, PartialEq)]
+use self::distance::DistanceBroadPhase;
+use self::interval_trees::IntervalTreesBroadPhase;
+use self::quadtree::QuadtreeBroadPhase;
+use self::spatial_hash::SpatialHashBroadPhase;
+use self::sweep_and_prune::SweepAndPruneBroadPhase;
+use self::verify::VerifyingBroadPhase;
+
+/// A pair of colliding bolas, identified by their indices in the arena,
+/// with `one` always being the lower of the two
+#[derive(Debug, Eq, Hash, PartialEq)]
 pub(crate) struct Collision {
     pub(crate) one: usize,
     pub(crate) two: usize,
 }
 
+impl Collision {
+    pub(crate) fn new(bola_one_idx: usize, bola_two_idx: usize) -> Self {
+        Self {
+            one: bola_one_idx.min(bola_two_idx),
+            two: bola_one_idx.max(bola_two_idx),
+        }
+    }
+}
+
 #[settings]
 #[derive(Copy)]
 pub(crate) enum CollisionDetectionAlgorithm {
     #[default]
     IntervalTrees,
     Distance,
+    /// Buckets bolas into a uniform grid of cells as wide as the largest
+    /// bola, so only bolas in neighbouring cells need to be compared
+    SpatialHash,
+    /// Keeps bolas sorted along the x axis between ticks, re-sorting them
+    /// incrementally as they move and only comparing bolas whose extents
+    /// along that axis overlap
+    SweepAndPrune,
+    /// Indexes bolas in a quadtree which subdivides more finely where bolas
+    /// are densely packed, so it copes well with clustered bolas
+    Quadtree,
+    /// Runs the algorithm set in `verified_collision_detection_algorithm`
+    /// alongside a brute-force check, logging and counting any pairs they
+    /// disagree on, and uses the brute-force result
+    Verify,
 }
 
-pub(crate) enum CollisionDetector {
-    Distance,
-    IntervalTrees {
-        overlaps_x: IntervalTree<i32, usize>,
-        overlaps_y: IntervalTree<i32, usize>,
-    },
-}
+/// Finds the pairs of bolas colliding in an arena, and the bolas touching
+/// any of its static obstacles. A broad phase lives as
+/// long as the arena it belongs to, so it can carry its index from one tick
+/// to the next rather than building it from scratch every time. Once built,
+/// the index is only read while querying, so bolas can be queried in
+/// parallel.
+pub(crate) trait BroadPhase: Sync {
+    /// Brings the index up to date with the positions of the given bolas,
+    /// called once per tick before querying for collisions
+    fn rebuild(&mut self, bolas: &[Bola]);
 
-impl From<CollisionDetectionAlgorithm> for CollisionDetector {
-    fn from(collision_detection_algorithm: CollisionDetectionAlgorithm) -> Self {
-        match collision_detection_algorithm {
-            CollisionDetectionAlgorithm::Distance => CollisionDetector::Distance,
-            CollisionDetectionAlgorithm::IntervalTrees => CollisionDetector::IntervalTrees {
-                overlaps_x: Default::default(),
-                overlaps_y: Default::default(),
-            },
-        }
-    }
-}
+    /// Adds the bola at the given index, called whenever a bola is added
+    /// to the arena
+    fn insert(&mut self, bolas: &[Bola], bola_idx: usize);
+
+    /// Drops the bola which was at the given index, called whenever a bola
+    /// is removed from the arena, after the bolas following it have moved
+    /// down an index. Broad phases rebuilt from scratch every tick have
+    /// nothing to do here.
+    fn remove(&mut self, _bolas: &[Bola], _bola_idx: usize) {}
+
+    /// Drops every bola, called when the arena is cleared
+    fn clear(&mut self) {}
+
+    /// Returns the collisions the bola at the given index is responsible
+    /// for reporting, every colliding pair must be reported by exactly one
+    /// of the two bolas in it
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_idx: usize) -> Vec<Collision>;
+
+    /// Indexes the arena's static obstacles, called whenever obstacles are
+    /// added to the arena rather than every tick as they never move
+    fn index_obstacles(&mut self, obstacles: &[Obstacle]);
 
-impl CollisionDetector {
-    pub(crate) fn detect_collisions_for_bola(
-        &mut self,
+    /// Returns the indices of the obstacles the bola at the given index is
+    /// touching
+    fn query_obstacles_for_bola(
+        &self,
         bolas: &[Bola],
-        bola_one_idx: usize,
-    ) -> Vec<Collision> {
-        match self {
-            Self::Distance => Self::detect_collisions_for_bola_distance(bolas, bola_one_idx),
-            Self::IntervalTrees {
-                overlaps_x,
-                overlaps_y,
-            } => Self::detect_collisions_for_bola_interval_trees(
-                bolas,
-                bola_one_idx,
-                overlaps_x,
-                overlaps_y,
-            ),
-        }
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize>;
+
+    /// Returns the indices of the indexed bolas whose bounding boxes overlap
+    /// the given region, used to find the bolas a fast bola might have hit
+    /// along its path. May include some which don't overlap it.
+    fn query_region(&self, bolas: &[Bola], region: &Aabb) -> Vec<usize>;
+
+    /// Returns the indices of the obstacles whose bounding boxes overlap the
+    /// given region. May include some which don't overlap it.
+    fn query_obstacles_in_region(&self, obstacles: &[Obstacle], region: &Aabb) -> Vec<usize>;
+
+    /// Returns every pair of indexed bolas which are colliding
+    fn query_pairs(&self, bolas: &[Bola]) -> HashSet<Collision> {
+        (0..bolas.len())
+            .flat_map(|bola_idx| self.query_pairs_for_bola(bolas, bola_idx))
+            .collect()
     }
 
-    fn detect_collisions_for_bola_distance(bolas: &[Bola], bola_one_idx: usize) -> Vec<Collision> {
-        let mut collisions = Vec::new();
-        let bola_one = &bolas[bola_one_idx];
-        for (bola_two_idx, bola_two) in bolas.iter().enumerate().skip(bola_one_idx + 1) {
-            let distance = ((bola_one.center.x - bola_two.center.x).powf(2.)
-                + (bola_one.center.y - bola_two.center.y).powf(2.))
-            .sqrt();
-
-            if (distance as i32) < (BOLA_COLLISION_RADIUS * 2) {
-                collisions.push(Collision {
-                    one: bola_one_idx,
-                    two: bola_two_idx,
-                });
-            }
-        }
+    /// Same as `query_pairs`, but queries bolas in parallel on the rayon
+    /// thread pool
+    fn par_query_pairs(&self, bolas: &[Bola]) -> HashSet<Collision> {
+        (0..bolas.len())
+            .into_par_iter()
+            .flat_map_iter(|bola_idx| self.query_pairs_for_bola(bolas, bola_idx))
+            .collect()
+    }
 
-        collisions
+    /// Returns the index of every bola touching an obstacle along with the
+    /// index of the obstacle
+    fn query_obstacles(&self, bolas: &[Bola], obstacles: &[Obstacle]) -> Vec<(usize, usize)> {
+        (0..bolas.len())
+            .flat_map(|bola_idx| {
+                self.query_obstacles_for_bola(bolas, obstacles, bola_idx)
+                    .into_iter()
+                    .map(move |obstacle_idx| (bola_idx, obstacle_idx))
+            })
+            .collect()
     }
 
-    fn detect_collisions_for_bola_interval_trees(
-        bolas: &[Bola],
-        bola_one_idx: usize,
-        overlaps_x: &mut IntervalTree<i32, usize>,
-        overlaps_y: &mut IntervalTree<i32, usize>,
-    ) -> Vec<Collision> {
-        let bola_one = &bolas[bola_one_idx];
-        let (x_range, y_range) = Self::get_location_ranges_for_bola(bola_one);
-        let collision_x: HashSet<usize> = overlaps_x.find(&x_range).map(|e| *e.data()).collect();
-        let collision_y: HashSet<usize> = overlaps_y.find(&y_range).map(|e| *e.data()).collect();
-
-        overlaps_x.insert(x_range, bola_one_idx);
-        overlaps_y.insert(y_range, bola_one_idx);
-
-        collision_x
-            .intersection(&collision_y)
-            .map(|bola_two_idx| Collision {
-                one: bola_one_idx,
-                two: *bola_two_idx,
+    /// Same as `query_obstacles`, but queries bolas in parallel on the rayon
+    /// thread pool
+    fn par_query_obstacles(&self, bolas: &[Bola], obstacles: &[Obstacle]) -> Vec<(usize, usize)> {
+        (0..bolas.len())
+            .into_par_iter()
+            .flat_map_iter(|bola_idx| {
+                self.query_obstacles_for_bola(bolas, obstacles, bola_idx)
+                    .into_iter()
+                    .map(move |obstacle_idx| (bola_idx, obstacle_idx))
             })
             .collect()
     }
+}
+
+/// Creates the broad phase for the given algorithm, `verified_algorithm` is
+/// the algorithm checked against brute force in `Verify` mode
+pub(crate) fn new_broad_phase(
+    algorithm: CollisionDetectionAlgorithm,
+    verified_algorithm: CollisionDetectionAlgorithm,
+) -> Box<dyn BroadPhase> {
+    match algorithm {
+        CollisionDetectionAlgorithm::Distance => Box::new(DistanceBroadPhase),
+        CollisionDetectionAlgorithm::IntervalTrees => Box::<IntervalTreesBroadPhase>::default(),
+        CollisionDetectionAlgorithm::SpatialHash => Box::<SpatialHashBroadPhase>::default(),
+        CollisionDetectionAlgorithm::SweepAndPrune => Box::<SweepAndPruneBroadPhase>::default(),
+        CollisionDetectionAlgorithm::Quadtree => Box::<QuadtreeBroadPhase>::default(),
+        CollisionDetectionAlgorithm::Verify => Box::new(VerifyingBroadPhase::new(
+            verified_algorithm,
+            new_broad_phase(verified_algorithm, CollisionDetectionAlgorithm::Distance),
+        )),
+    }
+}
+
+/// Bounding box of a bola where it is now
+pub(crate) fn get_bounds_for_bola(bola: &Bola) -> Aabb {
+    Aabb {
+        min_x: bola.center.x - bola.radius,
+        min_y: bola.center.y - bola.radius,
+        max_x: bola.center.x + bola.radius,
+        max_y: bola.center.y + bola.radius,
+    }
+}
+
+/// Narrow phase check of whether two bolas are touching
+pub(crate) fn bolas_collide(bola_one: &Bola, bola_two: &Bola) -> bool {
+    let distance = ((bola_one.center.x - bola_two.center.x).powf(2.)
+        + (bola_one.center.y - bola_two.center.y).powf(2.))
+    .sqrt();
+
+    distance < bola_one.radius + bola_two.radius
+}
+
+/// Returns how long before now two bolas first touched, given that they've
+/// been moving at their current velocities for the last `dt` seconds. This
+/// is the time of impact of their swept circles, so it's found both for
+/// bolas which are overlapping now and for bolas which passed through each
+/// other in that time. It's zero if they didn't touch in the last `dt`
+/// seconds, or were already overlapping `dt` seconds ago.
+pub(crate) fn time_since_impact(bola_one: &Bola, bola_two: &Bola, dt: f64) -> f64 {
+    let offset = (
+        bola_one.center.x - bola_two.center.x,
+        bola_one.center.y - bola_two.center.y,
+    );
+    let relative_velocity = (
+        bola_one.velocity.vel_x - bola_two.velocity.vel_x,
+        bola_one.velocity.vel_y - bola_two.velocity.vel_y,
+    );
+    let contact_distance = bola_one.radius + bola_two.radius;
+
+    // Solves |offset - relative_velocity * t| = contact_distance for t
+    let a = relative_velocity.0.powf(2.) + relative_velocity.1.powf(2.);
+    let b = offset.0 * relative_velocity.0 + offset.1 * relative_velocity.1;
+    let c = offset.0.powf(2.) + offset.1.powf(2.) - contact_distance.powf(2.);
+    let discriminant = b.powf(2.) - a * c;
+    if a == 0. || discriminant < 0. {
+        return 0.;
+    }
+
+    // Bolas which aren't overlapping now either touched in the past, if
+    // they're moving apart, or will only touch in the future
+    if c >= 0. && b <= 0. {
+        return 0.;
+    }
+
+    let time = (b + discriminant.sqrt()) / a;
+    if time > dt {
+        return 0.;
+    }
+
+    time.max(0.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Xorshift generator, so layouts are random but the same on every run
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
 
-    fn get_location_ranges_for_bola(bola: &Bola) -> (Range<i32>, Range<i32>) {
-        (
-            (bola.center.x.round() as i32) - BOLA_COLLISION_RADIUS
-                ..(bola.center.x.round() as i32) + BOLA_COLLISION_RADIUS,
-            (bola.center.y.round() as i32) - BOLA_COLLISION_RADIUS
-                ..(bola.center.y.round() as i32) + BOLA_COLLISION_RADIUS,
+        fn range(&mut self, min: f64, max: f64) -> f64 {
+            min + self.next() * (max - min)
+        }
+
+        fn index(&mut self, len: usize) -> usize {
+            (self.next() * len as f64) as usize
+        }
+    }
+
+    fn random_bola(rng: &mut Rng) -> Bola {
+        Bola::at(
+            rng.range(0., 1000.),
+            rng.range(0., 1000.),
+            rng.range(5., 40.),
         )
     }
+
+    /// Runs each broad phase through ticks in which bolas are added, removed
+    /// and moved like in an arena, checking both the serial and parallel
+    /// queries find exactly the pairs brute force does, and that querying a
+    /// region finds every bola brute force does
+    #[test]
+    fn broad_phases_match_brute_force() {
+        for &algorithm in &[
+            CollisionDetectionAlgorithm::IntervalTrees,
+            CollisionDetectionAlgorithm::SpatialHash,
+            CollisionDetectionAlgorithm::SweepAndPrune,
+            CollisionDetectionAlgorithm::Quadtree,
+        ] {
+            let mut rng = Rng(0x5eed_b01a5);
+            let mut region_rng = Rng(0x5eed_a1ea);
+            let mut broad_phase = new_broad_phase(algorithm, CollisionDetectionAlgorithm::Distance);
+            let mut bolas = Vec::new();
+
+            for tick in 0..20 {
+                // Bolas added straight after clearing the arena land on the
+                // indices of the bolas which were cleared
+                if tick == 10 {
+                    bolas.clear();
+                    broad_phase.clear();
+                }
+
+                for _ in 0..40 {
+                    bolas.push(random_bola(&mut rng));
+                    broad_phase.insert(&bolas, bolas.len() - 1);
+                }
+
+                for _ in 0..15 {
+                    let bola_idx = rng.index(bolas.len());
+                    bolas.remove(bola_idx);
+                    broad_phase.remove(&bolas, bola_idx);
+                }
+
+                // Large enough moves to shuffle the sweep and prune axis and
+                // carry bolas across quadtree nodes
+                for b in &mut bolas {
+                    b.center.x += rng.range(-30., 30.);
+                    b.center.y += rng.range(-30., 30.);
+                }
+
+                broad_phase.rebuild(&bolas);
+
+                let expected = DistanceBroadPhase.query_pairs(&bolas);
+                assert!(!expected.is_empty());
+                assert_eq!(
+                    broad_phase.query_pairs(&bolas),
+                    expected,
+                    "{:?} serial query on tick {}",
+                    algorithm,
+                    tick
+                );
+                assert_eq!(
+                    broad_phase.par_query_pairs(&bolas),
+                    expected,
+                    "{:?} parallel query on tick {}",
+                    algorithm,
+                    tick
+                );
+
+                // Regions may turn up a few extra bolas, but never miss any
+                let (x, y) = (region_rng.range(0., 1000.), region_rng.range(0., 1000.));
+                let region = Aabb {
+                    min_x: x,
+                    min_y: y,
+                    max_x: x + region_rng.range(0., 300.),
+                    max_y: y + region_rng.range(0., 300.),
+                };
+                let found: HashSet<_> = broad_phase
+                    .query_region(&bolas, &region)
+                    .into_iter()
+                    .collect();
+                for bola_idx in DistanceBroadPhase.query_region(&bolas, &region) {
+                    assert!(
+                        found.contains(&bola_idx),
+                        "{:?} region query on tick {} missed {:?}",
+                        algorithm,
+                        tick,
+                        bolas[bola_idx]
+                    );
+                }
+            }
+        }
+    }
+
+    /// Bolas far enough off the canvas have coordinates too large for an
+    /// i32, which broad phases mustn't lose track of
+    #[test]
+    fn broad_phases_find_far_away_bolas() {
+        for &algorithm in &[
+            CollisionDetectionAlgorithm::IntervalTrees,
+            CollisionDetectionAlgorithm::SpatialHash,
+            CollisionDetectionAlgorithm::SweepAndPrune,
+            CollisionDetectionAlgorithm::Quadtree,
+        ] {
+            let mut broad_phase = new_broad_phase(algorithm, CollisionDetectionAlgorithm::Distance);
+            let bolas = vec![
+                Bola::at(500., 500., 20.),
+                Bola::at(1e15, 1e15, 20.),
+                Bola::at(1e15 + 10., 1e15, 20.),
+                Bola::at(-1e15, -1e15, 20.),
+                Bola::at(-1e15, -1e15 - 10., 20.),
+            ];
+            for bola_idx in 0..bolas.len() {
+                broad_phase.insert(&bolas, bola_idx);
+            }
+            broad_phase.rebuild(&bolas);
+
+            let expected: HashSet<_> = vec![Collision::new(1, 2), Collision::new(3, 4)]
+                .into_iter()
+                .collect();
+            assert_eq!(broad_phase.query_pairs(&bolas), expected, "{:?}", algorithm);
+        }
+    }
 }
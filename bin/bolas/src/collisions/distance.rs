@@ -0,0 +1,59 @@
+use crate::bolas::Bola;
+use crate::collisions::{bolas_collide, get_bounds_for_bola, BroadPhase, Collision};
+use crate::obstacles::Obstacle;
+use crate::quadtree::Aabb;
+
+/// Compares every bola against every other bola, and every obstacle
+pub(crate) struct DistanceBroadPhase;
+
+impl BroadPhase for DistanceBroadPhase {
+    fn rebuild(&mut self, _: &[Bola]) {}
+
+    fn insert(&mut self, _: &[Bola], _: usize) {}
+
+    fn index_obstacles(&mut self, _: &[Obstacle]) {}
+
+    fn query_obstacles_for_bola(
+        &self,
+        bolas: &[Bola],
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize> {
+        let bola = &bolas[bola_idx];
+        obstacles
+            .iter()
+            .enumerate()
+            .filter(|(_, obstacle)| obstacle.contact(bola).is_some())
+            .map(|(obstacle_idx, _)| obstacle_idx)
+            .collect()
+    }
+
+    fn query_region(&self, bolas: &[Bola], region: &Aabb) -> Vec<usize> {
+        bolas
+            .iter()
+            .enumerate()
+            .filter(|(_, bola)| get_bounds_for_bola(bola).overlaps(region))
+            .map(|(bola_idx, _)| bola_idx)
+            .collect()
+    }
+
+    fn query_obstacles_in_region(&self, obstacles: &[Obstacle], region: &Aabb) -> Vec<usize> {
+        obstacles
+            .iter()
+            .enumerate()
+            .filter(|(_, obstacle)| obstacle.bounds().overlaps(region))
+            .map(|(obstacle_idx, _)| obstacle_idx)
+            .collect()
+    }
+
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_one_idx: usize) -> Vec<Collision> {
+        let bola_one = &bolas[bola_one_idx];
+        bolas
+            .iter()
+            .enumerate()
+            .skip(bola_one_idx + 1)
+            .filter(|(_, bola_two)| bolas_collide(bola_one, bola_two))
+            .map(|(bola_two_idx, _)| Collision::new(bola_one_idx, bola_two_idx))
+            .collect()
+    }
+}
@@ -0,0 +1,94 @@
+use crate::bolas::Bola;
+use crate::collisions::{bolas_collide, BroadPhase, Collision};
+use crate::obstacles::Obstacle;
+use crate::quadtree::Aabb;
+use bio::data_structures::interval_tree::IntervalTree;
+use std::collections::HashSet;
+use std::ops::Range;
+
+use super::obstacles::ObstacleIndex;
+
+/// Indexes the extent of each bola along the x and y axes in a pair of
+/// interval trees, bolas whose extents overlap on both axes might be colliding
+#[derive(Default)]
+pub(crate) struct IntervalTreesBroadPhase {
+    overlaps_x: IntervalTree<i64, usize>,
+    overlaps_y: IntervalTree<i64, usize>,
+    obstacles: ObstacleIndex,
+}
+
+impl IntervalTreesBroadPhase {
+    /// Extent of a bola along each axis, rounded outwards so that bolas
+    /// which are touching always have overlapping ranges
+    fn get_location_ranges_for_bola(bola: &Bola) -> (Range<i64>, Range<i64>) {
+        let range = |center: f64| {
+            (center - bola.radius).floor() as i64..(center + bola.radius).ceil() as i64
+        };
+
+        (range(bola.center.x), range(bola.center.y))
+    }
+}
+
+impl BroadPhase for IntervalTreesBroadPhase {
+    fn rebuild(&mut self, bolas: &[Bola]) {
+        self.overlaps_x = Default::default();
+        self.overlaps_y = Default::default();
+
+        for bola_idx in 0..bolas.len() {
+            self.insert(bolas, bola_idx);
+        }
+    }
+
+    fn insert(&mut self, bolas: &[Bola], bola_idx: usize) {
+        let (x_range, y_range) = Self::get_location_ranges_for_bola(&bolas[bola_idx]);
+        self.overlaps_x.insert(x_range, bola_idx);
+        self.overlaps_y.insert(y_range, bola_idx);
+    }
+
+    fn index_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.obstacles.rebuild(obstacles);
+    }
+
+    fn query_obstacles_for_bola(
+        &self,
+        bolas: &[Bola],
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize> {
+        self.obstacles.query(&bolas[bola_idx], obstacles)
+    }
+
+    fn query_region(&self, _: &[Bola], region: &Aabb) -> Vec<usize> {
+        let x_range = region.min_x.floor() as i64..region.max_x.ceil() as i64;
+        let y_range = region.min_y.floor() as i64..region.max_y.ceil() as i64;
+
+        let in_x: HashSet<usize> = self.overlaps_x.find(&x_range).map(|e| *e.data()).collect();
+        self.overlaps_y
+            .find(&y_range)
+            .map(|e| *e.data())
+            .filter(|bola_idx| in_x.contains(bola_idx))
+            .collect()
+    }
+
+    fn query_obstacles_in_region(&self, _: &[Obstacle], region: &Aabb) -> Vec<usize> {
+        self.obstacles.query_region(region)
+    }
+
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_one_idx: usize) -> Vec<Collision> {
+        let (x_range, y_range) = Self::get_location_ranges_for_bola(&bolas[bola_one_idx]);
+        let collision_x: HashSet<usize> = self
+            .overlaps_x
+            .find(&x_range)
+            .map(|e| *e.data())
+            .filter(|bola_two_idx| *bola_two_idx > bola_one_idx)
+            .collect();
+        let collision_y: HashSet<usize> =
+            self.overlaps_y.find(&y_range).map(|e| *e.data()).collect();
+
+        collision_x
+            .intersection(&collision_y)
+            .filter(|bola_two_idx| bolas_collide(&bolas[bola_one_idx], &bolas[**bola_two_idx]))
+            .map(|bola_two_idx| Collision::new(bola_one_idx, *bola_two_idx))
+            .collect()
+    }
+}
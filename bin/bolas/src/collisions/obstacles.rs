@@ -0,0 +1,38 @@
+use crate::bolas::Bola;
+use crate::collisions::get_bounds_for_bola;
+use crate::obstacles::Obstacle;
+use crate::quadtree::{Aabb, Quadtree};
+
+/// Indexes the bounding box of each static obstacle in a quadtree. Obstacles
+/// never move, so unlike the bolas the tree is only rebuilt when obstacles
+/// are added to the arena.
+#[derive(Default)]
+pub(crate) struct ObstacleIndex {
+    tree: Option<Quadtree>,
+}
+
+impl ObstacleIndex {
+    pub(crate) fn rebuild(&mut self, obstacles: &[Obstacle]) {
+        let mut tree = Quadtree::covering(obstacles.iter().map(Obstacle::bounds));
+        for (obstacle_idx, obstacle) in obstacles.iter().enumerate() {
+            tree.insert(obstacle.bounds(), obstacle_idx);
+        }
+
+        self.tree = Some(tree);
+    }
+
+    pub(crate) fn query(&self, bola: &Bola, obstacles: &[Obstacle]) -> Vec<usize> {
+        self.query_region(&get_bounds_for_bola(bola))
+            .into_iter()
+            .filter(|obstacle_idx| obstacles[*obstacle_idx].contact(bola).is_some())
+            .collect()
+    }
+
+    /// Returns the indices of the obstacles whose bounding boxes overlap the
+    /// given region
+    pub(crate) fn query_region(&self, region: &Aabb) -> Vec<usize> {
+        self.tree
+            .as_ref()
+            .map_or_else(Vec::new, |tree| tree.find(region))
+    }
+}
@@ -0,0 +1,69 @@
+use crate::bolas::Bola;
+use crate::collisions::{bolas_collide, get_bounds_for_bola, BroadPhase, Collision};
+use crate::obstacles::Obstacle;
+use crate::quadtree::{Aabb, Quadtree};
+
+use super::obstacles::ObstacleIndex;
+
+/// Indexes the bounding box of each bola in a quadtree, which subdivides
+/// more finely where bolas are densely packed
+#[derive(Default)]
+pub(crate) struct QuadtreeBroadPhase {
+    tree: Option<Quadtree>,
+    obstacles: ObstacleIndex,
+}
+
+impl BroadPhase for QuadtreeBroadPhase {
+    fn rebuild(&mut self, bolas: &[Bola]) {
+        self.tree = Some(Quadtree::covering(bolas.iter().map(get_bounds_for_bola)));
+
+        for bola_idx in 0..bolas.len() {
+            self.insert(bolas, bola_idx);
+        }
+    }
+
+    fn insert(&mut self, bolas: &[Bola], bola_idx: usize) {
+        // Bolas outside of the tree's bounds are kept in the root node, so
+        // they're still found until the next rebuild grows the tree
+        if let Some(tree) = &mut self.tree {
+            tree.insert(get_bounds_for_bola(&bolas[bola_idx]), bola_idx);
+        }
+    }
+
+    fn index_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.obstacles.rebuild(obstacles);
+    }
+
+    fn query_obstacles_for_bola(
+        &self,
+        bolas: &[Bola],
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize> {
+        self.obstacles.query(&bolas[bola_idx], obstacles)
+    }
+
+    fn query_region(&self, _: &[Bola], region: &Aabb) -> Vec<usize> {
+        self.tree
+            .as_ref()
+            .map_or_else(Vec::new, |tree| tree.find(region))
+    }
+
+    fn query_obstacles_in_region(&self, _: &[Obstacle], region: &Aabb) -> Vec<usize> {
+        self.obstacles.query_region(region)
+    }
+
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_one_idx: usize) -> Vec<Collision> {
+        let Some(tree) = &self.tree else {
+            return Vec::new();
+        };
+
+        let bola_one = &bolas[bola_one_idx];
+        tree.find(&get_bounds_for_bola(bola_one))
+            .into_iter()
+            .filter(|bola_two_idx| *bola_two_idx > bola_one_idx)
+            .filter(|bola_two_idx| bolas_collide(bola_one, &bolas[*bola_two_idx]))
+            .map(|bola_two_idx| Collision::new(bola_one_idx, bola_two_idx))
+            .collect()
+    }
+}
@@ -0,0 +1,129 @@
+use crate::bolas::Bola;
+use crate::collisions::{bolas_collide, get_bounds_for_bola, BroadPhase, Collision};
+use crate::obstacles::Obstacle;
+use crate::quadtree::Aabb;
+use std::collections::HashMap;
+
+use super::obstacles::ObstacleIndex;
+
+/// Buckets bolas into a uniform grid of cells as wide as the largest bola,
+/// so any bola colliding with another must have its center in the same
+/// cell or one of the neighbouring ones
+#[derive(Default)]
+pub(crate) struct SpatialHashBroadPhase {
+    cell_size: f64,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    obstacles: ObstacleIndex,
+}
+
+impl SpatialHashBroadPhase {
+    fn get_cell_for_bola(&self, bola: &Bola) -> (i32, i32) {
+        self.get_cell_for_point(bola.center.x, bola.center.y)
+    }
+
+    fn get_cell_for_point(&self, x: f64, y: f64) -> (i32, i32) {
+        (
+            (x / self.cell_size).floor() as i32,
+            (y / self.cell_size).floor() as i32,
+        )
+    }
+}
+
+impl BroadPhase for SpatialHashBroadPhase {
+    fn rebuild(&mut self, bolas: &[Bola]) {
+        self.cell_size = bolas.iter().map(|b| b.radius * 2.).fold(1., f64::max);
+        self.cells.clear();
+
+        for bola_idx in 0..bolas.len() {
+            self.insert(bolas, bola_idx);
+        }
+    }
+
+    fn insert(&mut self, bolas: &[Bola], bola_idx: usize) {
+        // Bolas larger than the current cells can't be bucketed correctly
+        // until the next rebuild resizes the grid, they'll be picked up then
+        if self.cell_size < bolas[bola_idx].radius * 2. {
+            return;
+        }
+
+        let cell = self.get_cell_for_bola(&bolas[bola_idx]);
+        self.cells.entry(cell).or_default().push(bola_idx);
+    }
+
+    fn index_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.obstacles.rebuild(obstacles);
+    }
+
+    fn query_obstacles_for_bola(
+        &self,
+        bolas: &[Bola],
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize> {
+        self.obstacles.query(&bolas[bola_idx], obstacles)
+    }
+
+    /// Bolas are no wider than a cell, so any overlapping the region have
+    /// their centers in a cell within one cell of it
+    fn query_region(&self, bolas: &[Bola], region: &Aabb) -> Vec<usize> {
+        let (min_x, min_y) = self.get_cell_for_point(region.min_x, region.min_y);
+        let (max_x, max_y) = self.get_cell_for_point(region.max_x, region.max_y);
+        let (min_x, min_y) = (min_x.saturating_sub(1), min_y.saturating_sub(1));
+        let (max_x, max_y) = (max_x.saturating_add(1), max_y.saturating_add(1));
+        let in_region =
+            |(x, y): &(i32, i32)| (min_x..=max_x).contains(x) && (min_y..=max_y).contains(y);
+
+        // Large regions cover more cells than are occupied, in which case
+        // it's cheaper to go through the occupied ones
+        let region_cells =
+            (i64::from(max_x) - i64::from(min_x) + 1) * (i64::from(max_y) - i64::from(min_y) + 1);
+        let candidates: Vec<usize> = if region_cells > self.cells.len() as i64 {
+            self.cells
+                .iter()
+                .filter(|(cell, _)| in_region(cell))
+                .flat_map(|(_, bolas)| bolas.iter().copied())
+                .collect()
+        } else {
+            (min_x..=max_x)
+                .flat_map(|x| (min_y..=max_y).map(move |y| (x, y)))
+                .filter_map(|cell| self.cells.get(&cell))
+                .flat_map(|bolas| bolas.iter().copied())
+                .collect()
+        };
+
+        candidates
+            .into_iter()
+            .filter(|bola_idx| get_bounds_for_bola(&bolas[*bola_idx]).overlaps(region))
+            .collect()
+    }
+
+    fn query_obstacles_in_region(&self, _: &[Obstacle], region: &Aabb) -> Vec<usize> {
+        self.obstacles.query_region(region)
+    }
+
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_one_idx: usize) -> Vec<Collision> {
+        let bola_one = &bolas[bola_one_idx];
+        let (cell_x, cell_y) = self.get_cell_for_bola(bola_one);
+        let mut collisions = Vec::new();
+
+        // Cells of bolas far enough away saturate at the limits of an i32,
+        // so the neighbouring cells mustn't overflow past them
+        for neighbour_x in cell_x.saturating_sub(1)..=cell_x.saturating_add(1) {
+            for neighbour_y in cell_y.saturating_sub(1)..=cell_y.saturating_add(1) {
+                let Some(neighbours) = self.cells.get(&(neighbour_x, neighbour_y)) else {
+                    continue;
+                };
+
+                collisions.extend(
+                    neighbours
+                        .iter()
+                        .filter(|bola_two_idx| **bola_two_idx > bola_one_idx)
+                        .filter(|bola_two_idx| bolas_collide(bola_one, &bolas[**bola_two_idx]))
+                        .map(|bola_two_idx| Collision::new(bola_one_idx, *bola_two_idx)),
+                );
+            }
+        }
+
+        collisions
+    }
+}
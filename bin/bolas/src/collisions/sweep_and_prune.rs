@@ -0,0 +1,174 @@
+use crate::bolas::Bola;
+use crate::collisions::{bolas_collide, get_bounds_for_bola, BroadPhase, Collision};
+use crate::obstacles::Obstacle;
+use crate::quadtree::Aabb;
+
+use super::obstacles::ObstacleIndex;
+
+/// Keeps bolas sorted by the left edge of their extent along the x axis.
+/// The sorted axis is kept between ticks and, since bolas move very little
+/// from one tick to the next, it's re-sorted with an insertion sort which
+/// does close to linear work on the nearly sorted data.
+#[derive(Default)]
+pub(crate) struct SweepAndPruneBroadPhase {
+    axis: Vec<usize>,
+
+    /// Position of each bola on the axis, indexed by bola
+    positions: Vec<usize>,
+
+    /// Width of the widest bola, so a query for a region knows how far
+    /// left of it the bolas overlapping it can start
+    max_width: f64,
+
+    obstacles: ObstacleIndex,
+}
+
+fn min_x(bola: &Bola) -> f64 {
+    bola.center.x - bola.radius
+}
+
+impl BroadPhase for SweepAndPruneBroadPhase {
+    fn rebuild(&mut self, bolas: &[Bola]) {
+        // Every bola added to or removed from the arena is inserted into or
+        // removed from the axis as it happens, so the axis always holds each
+        // bola exactly once and only needs re-sorting
+        for i in 1..self.axis.len() {
+            let mut j = i;
+            while j > 0 && min_x(&bolas[self.axis[j - 1]]) > min_x(&bolas[self.axis[j]]) {
+                self.axis.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        self.max_width = bolas.iter().map(|b| b.radius * 2.).fold(0., f64::max);
+        self.positions.resize(bolas.len(), 0);
+        for (axis_idx, bola_idx) in self.axis.iter().copied().enumerate() {
+            self.positions[bola_idx] = axis_idx;
+        }
+    }
+
+    fn insert(&mut self, _: &[Bola], bola_idx: usize) {
+        // New bolas are sorted into place on the next rebuild
+        self.axis.push(bola_idx);
+    }
+
+    fn remove(&mut self, _: &[Bola], bola_idx: usize) {
+        self.axis.retain(|idx| *idx != bola_idx);
+        for idx in &mut self.axis {
+            if *idx > bola_idx {
+                *idx -= 1;
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.axis.clear();
+        self.positions.clear();
+    }
+
+    fn index_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.obstacles.rebuild(obstacles);
+    }
+
+    fn query_obstacles_for_bola(
+        &self,
+        bolas: &[Bola],
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize> {
+        self.obstacles.query(&bolas[bola_idx], obstacles)
+    }
+
+    fn query_region(&self, bolas: &[Bola], region: &Aabb) -> Vec<usize> {
+        let start = self
+            .axis
+            .partition_point(|bola_idx| min_x(&bolas[*bola_idx]) < region.min_x - self.max_width);
+        let end = self
+            .axis
+            .partition_point(|bola_idx| min_x(&bolas[*bola_idx]) <= region.max_x);
+
+        self.axis[start..end.max(start)]
+            .iter()
+            .copied()
+            .filter(|bola_idx| get_bounds_for_bola(&bolas[*bola_idx]).overlaps(region))
+            .collect()
+    }
+
+    fn query_obstacles_in_region(&self, _: &[Obstacle], region: &Aabb) -> Vec<usize> {
+        self.obstacles.query_region(region)
+    }
+
+    /// Each bola reports collisions with the bolas after it on the axis,
+    /// sweeping forward until it reaches one that starts past its right edge
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_one_idx: usize) -> Vec<Collision> {
+        let Some(&axis_idx) = self.positions.get(bola_one_idx) else {
+            return Vec::new();
+        };
+
+        let bola_one = &bolas[bola_one_idx];
+        let max_x = bola_one.center.x + bola_one.radius;
+
+        self.axis[axis_idx + 1..]
+            .iter()
+            .copied()
+            .take_while(|bola_two_idx| min_x(&bolas[*bola_two_idx]) <= max_x)
+            .filter(|bola_two_idx| bolas_collide(bola_one, &bolas[*bola_two_idx]))
+            .map(|bola_two_idx| Collision::new(bola_one_idx, bola_two_idx))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn assert_consistent(broad_phase: &SweepAndPruneBroadPhase, bolas: &[Bola]) {
+        let mut on_axis = broad_phase.axis.clone();
+        on_axis.sort_unstable();
+        assert_eq!(on_axis, (0..bolas.len()).collect::<Vec<_>>());
+
+        assert!(broad_phase
+            .axis
+            .windows(2)
+            .all(|w| min_x(&bolas[w[0]]) <= min_x(&bolas[w[1]])));
+        for (axis_idx, bola_idx) in broad_phase.axis.iter().copied().enumerate() {
+            assert_eq!(broad_phase.positions[bola_idx], axis_idx);
+        }
+    }
+
+    #[test]
+    fn resorts_after_moves_and_removals() {
+        let mut bolas: Vec<_> = (0..10)
+            .map(|i| Bola::at(i as f64 * 100., 0., 10.))
+            .collect();
+        let mut broad_phase = SweepAndPruneBroadPhase::default();
+        for bola_idx in 0..bolas.len() {
+            broad_phase.insert(&bolas, bola_idx);
+        }
+        broad_phase.rebuild(&bolas);
+        assert_consistent(&broad_phase, &bolas);
+
+        // Far more than bolas move in a tick, reversing the whole axis
+        for bola in &mut bolas {
+            bola.center.x = 1000. - bola.center.x;
+        }
+        broad_phase.rebuild(&bolas);
+        assert_consistent(&broad_phase, &bolas);
+
+        // Removing bolas shifts the index of every bola after them
+        for &bola_idx in &[7, 0, 3] {
+            bolas.remove(bola_idx);
+            broad_phase.remove(&bolas, bola_idx);
+        }
+        bolas.push(Bola::at(410., 0., 10.));
+        broad_phase.insert(&bolas, bolas.len() - 1);
+        broad_phase.rebuild(&bolas);
+        assert_consistent(&broad_phase, &bolas);
+
+        // The new bola only overlaps the one left at 400
+        let at_400 = bolas.iter().position(|b| b.center.x == 400.).unwrap();
+        let expected: HashSet<_> = [Collision::new(at_400, bolas.len() - 1)].into();
+        assert_eq!(broad_phase.query_pairs(&bolas), expected);
+    }
+}
@@ -0,0 +1,120 @@
+use crate::bolas::Bola;
+use crate::collisions::{BroadPhase, Collision, CollisionDetectionAlgorithm};
+use crate::metrics::metrics;
+use crate::obstacles::Obstacle;
+use crate::quadtree::Aabb;
+use foundations::telemetry::log;
+use std::collections::HashSet;
+
+use super::distance::DistanceBroadPhase;
+
+/// Most of the missed and spurious pairs included in each warning, the
+/// counts are always logged in full
+const MAX_LOGGED_MISMATCHES: usize = 5;
+
+/// Runs another broad phase alongside a brute-force one, reporting any pairs
+/// on which they disagree, and always using the brute-force result. Meant
+/// for validating new broad phases against real traffic.
+pub(crate) struct VerifyingBroadPhase {
+    algorithm: CollisionDetectionAlgorithm,
+    verified: Box<dyn BroadPhase>,
+    reference: DistanceBroadPhase,
+}
+
+impl VerifyingBroadPhase {
+    pub(crate) fn new(
+        algorithm: CollisionDetectionAlgorithm,
+        verified: Box<dyn BroadPhase>,
+    ) -> Self {
+        Self {
+            algorithm,
+            verified,
+            reference: DistanceBroadPhase,
+        }
+    }
+
+    fn compare(
+        &self,
+        verified: HashSet<Collision>,
+        reference: HashSet<Collision>,
+    ) -> HashSet<Collision> {
+        // Pairs found by brute force that the verified algorithm didn't find,
+        // and those it reported that aren't actually colliding
+        let missed: Vec<_> = reference.difference(&verified).collect();
+        let spurious: Vec<_> = verified.difference(&reference).collect();
+
+        if !missed.is_empty() || !spurious.is_empty() {
+            metrics::collision_detection_mismatches_total("missed").inc_by(missed.len() as u64);
+            metrics::collision_detection_mismatches_total("spurious").inc_by(spurious.len() as u64);
+
+            log::warn!(
+                "Collision detection algorithm disagreed with brute force";
+                "algorithm" => ?self.algorithm,
+                "missed" => missed.len(),
+                "spurious" => spurious.len(),
+                "missed_sample" => ?&missed[..missed.len().min(MAX_LOGGED_MISMATCHES)],
+                "spurious_sample" => ?&spurious[..spurious.len().min(MAX_LOGGED_MISMATCHES)],
+            );
+        }
+
+        reference
+    }
+}
+
+impl BroadPhase for VerifyingBroadPhase {
+    fn rebuild(&mut self, bolas: &[Bola]) {
+        self.verified.rebuild(bolas);
+    }
+
+    fn insert(&mut self, bolas: &[Bola], bola_idx: usize) {
+        self.verified.insert(bolas, bola_idx);
+    }
+
+    fn remove(&mut self, bolas: &[Bola], bola_idx: usize) {
+        self.verified.remove(bolas, bola_idx);
+    }
+
+    fn clear(&mut self) {
+        self.verified.clear();
+    }
+
+    fn index_obstacles(&mut self, obstacles: &[Obstacle]) {
+        self.verified.index_obstacles(obstacles);
+    }
+
+    fn query_obstacles_for_bola(
+        &self,
+        bolas: &[Bola],
+        obstacles: &[Obstacle],
+        bola_idx: usize,
+    ) -> Vec<usize> {
+        self.reference
+            .query_obstacles_for_bola(bolas, obstacles, bola_idx)
+    }
+
+    fn query_pairs_for_bola(&self, bolas: &[Bola], bola_idx: usize) -> Vec<Collision> {
+        self.reference.query_pairs_for_bola(bolas, bola_idx)
+    }
+
+    fn query_region(&self, bolas: &[Bola], region: &Aabb) -> Vec<usize> {
+        self.reference.query_region(bolas, region)
+    }
+
+    fn query_obstacles_in_region(&self, obstacles: &[Obstacle], region: &Aabb) -> Vec<usize> {
+        self.reference.query_obstacles_in_region(obstacles, region)
+    }
+
+    fn query_pairs(&self, bolas: &[Bola]) -> HashSet<Collision> {
+        self.compare(
+            self.verified.query_pairs(bolas),
+            self.reference.query_pairs(bolas),
+        )
+    }
+
+    fn par_query_pairs(&self, bolas: &[Bola]) -> HashSet<Collision> {
+        self.compare(
+            self.verified.par_query_pairs(bolas),
+            self.reference.par_query_pairs(bolas),
+        )
+    }
+}
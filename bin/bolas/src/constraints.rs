@@ -0,0 +1,268 @@
+use crate::bolas::{mass_shares, Bola, BolaId, Point};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Number of times the rigid constraints are solved each substep. Solving
+/// one constraint can stretch its neighbours in a chain, so they're solved
+/// repeatedly to let corrections propagate along the chain.
+const CONSTRAINT_ITERATIONS: usize = 4;
+
+/// Bounds on the stiffness and damping a client may give a spring
+pub(crate) const MAX_SPRING_STIFFNESS: f64 = 10000.;
+pub(crate) const MAX_SPRING_DAMPING: f64 = 1000.;
+
+/// Something holding bolas, identified by their ids, at a
+/// distance from each other or from a fixed point. Constraints are sent to
+/// clients so they can draw them, tagged with their kind.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "k", rename_all = "snake_case")]
+pub(crate) enum Constraint {
+    /// Goes slack when the bolas move closer together, but never lets them
+    /// move further apart than its length
+    Rope {
+        #[serde(rename = "a")]
+        one: BolaId,
+        #[serde(rename = "b")]
+        two: BolaId,
+        #[serde(skip_serializing)]
+        length: f64,
+    },
+    /// Holds the bolas exactly its length apart, like a rigid rod
+    DistanceJoint {
+        #[serde(rename = "a")]
+        one: BolaId,
+        #[serde(rename = "b")]
+        two: BolaId,
+        #[serde(skip_serializing)]
+        length: f64,
+    },
+    /// Pulls the bolas towards being its length apart with a force
+    /// proportional to how far it's stretched or compressed, in pixels per
+    /// second squared per pixel for a bola of mass 1, while damping slows
+    /// down the bolas moving towards or away from each other
+    Spring {
+        #[serde(rename = "a")]
+        one: BolaId,
+        #[serde(rename = "b")]
+        two: BolaId,
+        #[serde(skip_serializing)]
+        length: f64,
+        #[serde(skip_serializing)]
+        stiffness: f64,
+        #[serde(skip_serializing)]
+        damping: f64,
+    },
+    /// Holds a bola exactly its length away from a fixed point, so it swings
+    /// around it like a pendulum, or holds it in place with a length of 0
+    PinJoint {
+        #[serde(rename = "a")]
+        bola: BolaId,
+        #[serde(rename = "p")]
+        anchor: Point,
+        #[serde(skip_serializing)]
+        length: f64,
+    },
+}
+
+impl Constraint {
+    /// Whether the constraint acts on the bola with the given id
+    pub(crate) fn is_attached_to(&self, bola_id: BolaId) -> bool {
+        match *self {
+            Self::Rope { one, two, .. }
+            | Self::DistanceJoint { one, two, .. }
+            | Self::Spring { one, two, .. } => one == bola_id || two == bola_id,
+            Self::PinJoint { bola, .. } => bola == bola_id,
+        }
+    }
+
+    /// Constraints are removed along with the bolas they're attached to, so
+    /// every id they refer to is always in `indices`
+    fn solve_position(&self, bolas: &mut [Bola], indices: &HashMap<BolaId, usize>) {
+        match *self {
+            Self::Rope { one, two, length } => {
+                solve_distance(bolas, indices[&one], indices[&two], length, true)
+            }
+            Self::DistanceJoint { one, two, length } => {
+                solve_distance(bolas, indices[&one], indices[&two], length, false)
+            }
+            Self::PinJoint {
+                bola,
+                ref anchor,
+                length,
+            } => solve_pin(&mut bolas[indices[&bola]], anchor, length),
+            Self::Spring { .. } => {}
+        }
+    }
+
+    fn solve_velocity(&self, bolas: &mut [Bola], indices: &HashMap<BolaId, usize>, dt: f64) {
+        if let Self::Spring {
+            one,
+            two,
+            length,
+            stiffness,
+            damping,
+        } = *self
+        {
+            solve_spring(
+                bolas,
+                indices[&one],
+                indices[&two],
+                length,
+                stiffness,
+                damping,
+                dt,
+            );
+        }
+    }
+}
+
+/// Direction from the first bola to the second along with the distance
+/// between them, which is `None` when they're on top of each other
+fn normal_between(bola_one: &Bola, bola_two: &Bola) -> Option<((f64, f64), f64)> {
+    let offset = (
+        bola_two.center.x - bola_one.center.x,
+        bola_two.center.y - bola_one.center.y,
+    );
+    let distance = (offset.0.powf(2.) + offset.1.powf(2.)).sqrt();
+    if distance == 0. {
+        return None;
+    }
+
+    Some(((offset.0 / distance, offset.1 / distance), distance))
+}
+
+/// Moves a pair of bolas back to the given distance apart, moving each by an
+/// amount proportional to the other's share of their total mass, and removes
+/// the part of their relative velocity which would pull them away from it.
+/// Slack constraints only pull the bolas together, never push them apart.
+fn solve_distance(bolas: &mut [Bola], one: usize, two: usize, length: f64, slack: bool) {
+    let bola_one = &bolas[one];
+    let bola_two = &bolas[two];
+    let Some((normal, distance)) = normal_between(bola_one, bola_two) else {
+        return;
+    };
+
+    let stretch = distance - length;
+    if slack && stretch <= 0. {
+        return;
+    }
+
+    let Some((share_one, share_two)) = mass_shares(bola_one, bola_two) else {
+        return;
+    };
+
+    // The constraint is inelastic, so it takes away all of the relative
+    // velocity stretching it, leaving the bolas to swing around each other
+    let separating_speed = (bola_two.velocity.vel_x - bola_one.velocity.vel_x) * normal.0
+        + (bola_two.velocity.vel_y - bola_one.velocity.vel_y) * normal.1;
+    let speed = if slack {
+        separating_speed.max(0.)
+    } else {
+        separating_speed
+    };
+
+    let bola_one = &mut bolas[one];
+    bola_one.center.x += normal.0 * stretch * share_one;
+    bola_one.center.y += normal.1 * stretch * share_one;
+    bola_one.velocity.vel_x += normal.0 * speed * share_one;
+    bola_one.velocity.vel_y += normal.1 * speed * share_one;
+
+    let bola_two = &mut bolas[two];
+    bola_two.center.x -= normal.0 * stretch * share_two;
+    bola_two.center.y -= normal.1 * stretch * share_two;
+    bola_two.velocity.vel_x -= normal.0 * speed * share_two;
+    bola_two.velocity.vel_y -= normal.1 * speed * share_two;
+}
+
+/// Moves a bola back to the given distance from a fixed point and removes
+/// its velocity towards or away from the point
+fn solve_pin(bola: &mut Bola, anchor: &Point, length: f64) {
+    if bola.grab.is_some() {
+        return;
+    }
+
+    let offset = (bola.center.x - anchor.x, bola.center.y - anchor.y);
+    let distance = (offset.0.powf(2.) + offset.1.powf(2.)).sqrt();
+    if distance == 0. || length == 0. {
+        bola.center.x = anchor.x;
+        bola.center.y = anchor.y;
+        bola.velocity.vel_x = 0.;
+        bola.velocity.vel_y = 0.;
+        return;
+    }
+
+    let normal = (offset.0 / distance, offset.1 / distance);
+    let radial_speed = bola.velocity.vel_x * normal.0 + bola.velocity.vel_y * normal.1;
+
+    bola.center.x = anchor.x + normal.0 * length;
+    bola.center.y = anchor.y + normal.1 * length;
+    bola.velocity.vel_x -= normal.0 * radial_speed;
+    bola.velocity.vel_y -= normal.1 * radial_speed;
+}
+
+/// Applies the spring's force to a pair of bolas over a timestep. The force
+/// is evaluated at the end of the timestep rather than the start, which
+/// keeps stiff springs between light bolas from blowing up.
+fn solve_spring(
+    bolas: &mut [Bola],
+    one: usize,
+    two: usize,
+    length: f64,
+    stiffness: f64,
+    damping: f64,
+    dt: f64,
+) {
+    let bola_one = &bolas[one];
+    let bola_two = &bolas[two];
+    let Some((normal, distance)) = normal_between(bola_one, bola_two) else {
+        return;
+    };
+
+    let inverse_mass_one = bola_one.inverse_mass();
+    let inverse_mass_two = bola_two.inverse_mass();
+    let inverse_mass = inverse_mass_one + inverse_mass_two;
+    if inverse_mass == 0. {
+        return;
+    }
+
+    // Solving for the relative velocity along the spring after the step,
+    // `v'`, from `v' = v - dt * (k * (x + dt * v') + c * v') / m` where `m`
+    // is the reduced mass of the pair
+    let stretch = distance - length;
+    let speed = (bola_two.velocity.vel_x - bola_one.velocity.vel_x) * normal.0
+        + (bola_two.velocity.vel_y - bola_one.velocity.vel_y) * normal.1;
+    let new_speed = (speed - dt * inverse_mass * stiffness * stretch)
+        / (1. + dt * inverse_mass * (damping + dt * stiffness));
+    let change = new_speed - speed;
+
+    let bola_one = &mut bolas[one];
+    bola_one.velocity.vel_x -= normal.0 * change * inverse_mass_one / inverse_mass;
+    bola_one.velocity.vel_y -= normal.1 * change * inverse_mass_one / inverse_mass;
+
+    let bola_two = &mut bolas[two];
+    bola_two.velocity.vel_x += normal.0 * change * inverse_mass_two / inverse_mass;
+    bola_two.velocity.vel_y += normal.1 * change * inverse_mass_two / inverse_mass;
+}
+
+/// Solves every constraint in the arena over a substep of the given length,
+/// `indices` maps the id of each bola to its index in `bolas`
+pub(crate) fn solve_constraints(
+    constraints: &[Constraint],
+    bolas: &mut [Bola],
+    indices: &HashMap<BolaId, usize>,
+    dt: f64,
+) {
+    if constraints.is_empty() {
+        return;
+    }
+
+    for c in constraints {
+        c.solve_velocity(bolas, indices, dt);
+    }
+
+    for _ in 0..CONSTRAINT_ITERATIONS {
+        for c in constraints {
+            c.solve_position(bolas, indices);
+        }
+    }
+}
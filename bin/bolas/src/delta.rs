@@ -0,0 +1,329 @@
+use crate::bolas::{Bola, BolaId};
+use crate::constraints::Constraint;
+use crate::obstacles::Obstacle;
+use crate::physics::PhysicsParams;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Copy of everything clients draw from an arena, taken once per refresh and
+/// shared between every client in the arena
+pub(crate) struct ArenaSnapshot {
+    pub(crate) id: Uuid,
+
+    /// Number of timesteps the arena had been simulated for when the
+    /// snapshot was taken
+    pub(crate) tick: u64,
+
+    /// Server time the snapshot was taken at, in milliseconds since the unix
+    /// epoch
+    pub(crate) time: u64,
+    pub(crate) bolas: Vec<Bola>,
+    pub(crate) links: Vec<Constraint>,
+    pub(crate) obstacles: Vec<Obstacle>,
+    pub(crate) physics: PhysicsParams,
+}
+
+/// State sent to a client, tagged with its kind. Keyframes carry the whole
+/// arena, deltas only what changed since the previous update the client was
+/// sent.
+#[derive(Serialize)]
+#[serde(tag = "k", rename_all = "snake_case")]
+pub(crate) enum StateUpdate<'a> {
+    Keyframe {
+        arena: Uuid,
+        tick: u64,
+        time: u64,
+
+        /// Size in pixels of a step in the quantized positions of moved
+        /// bolas in the deltas following the keyframe
+        precision: f64,
+        bolas: &'a [Bola],
+        links: &'a [Constraint],
+        obstacles: &'a [Obstacle],
+    },
+    Delta {
+        tick: u64,
+        time: u64,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        added: Vec<&'a Bola>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        removed: Vec<BolaId>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        moved: Vec<MovedBola>,
+
+        /// Only sent when they've changed, in which case the whole list is
+        #[serde(skip_serializing_if = "Option::is_none")]
+        links: Option<&'a [Constraint]>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        obstacles: Option<&'a [Obstacle]>,
+    },
+}
+
+/// New position and velocity of a bola, in multiples of the precision sent
+/// in the last keyframe, and of the precision per second for the velocity
+#[derive(Serialize)]
+pub(crate) struct MovedBola {
+    #[serde(rename = "i")]
+    id: BolaId,
+    x: i64,
+    y: i64,
+    vx: i64,
+    vy: i64,
+}
+
+/// Position and velocity of a bola, quantized to the tracker's precision
+#[derive(Clone, Copy, PartialEq)]
+struct QuantizedBola {
+    x: i64,
+    y: i64,
+    vx: i64,
+    vy: i64,
+}
+
+/// Keeps track of the state a single client has been sent, to work out the
+/// delta to send it for each new snapshot of its arena
+pub(crate) struct DeltaTracker {
+    precision: f64,
+    keyframe_interval: u32,
+    updates_since_keyframe: u32,
+
+    /// Quantized position and velocity of every bola the client knows
+    /// about, as last sent to it. Comparing against these rather than the
+    /// previous snapshot keeps bolas creeping along by less than the
+    /// precision each update from never being sent at all.
+    sent_bolas: HashMap<BolaId, QuantizedBola>,
+
+    last_snapshot: Option<Arc<ArenaSnapshot>>,
+}
+
+impl DeltaTracker {
+    pub(crate) fn new(precision: f64, keyframe_interval: u32) -> Self {
+        Self {
+            precision,
+            keyframe_interval,
+            updates_since_keyframe: 0,
+            sent_bolas: Default::default(),
+            last_snapshot: None,
+        }
+    }
+
+    /// Works out the update to send the client for a new snapshot, which is
+    /// a keyframe if the client hasn't been sent one for a while, or `None`
+    /// if nothing the client can see has changed
+    pub(crate) fn update<'a>(
+        &mut self,
+        snapshot: &'a Arc<ArenaSnapshot>,
+    ) -> Option<StateUpdate<'a>> {
+        let last_snapshot = self.last_snapshot.replace(Arc::clone(snapshot));
+
+        let keyframe_due = self.updates_since_keyframe + 1 >= self.keyframe_interval;
+        let Some(last_snapshot) = last_snapshot.filter(|_| !keyframe_due) else {
+            self.updates_since_keyframe = 0;
+            self.sent_bolas = snapshot
+                .bolas
+                .iter()
+                .map(|b| (b.id, self.quantize(b)))
+                .collect();
+
+            return Some(StateUpdate::Keyframe {
+                arena: snapshot.id,
+                tick: snapshot.tick,
+                time: snapshot.time,
+                precision: self.precision,
+                bolas: &snapshot.bolas,
+                links: &snapshot.links,
+                obstacles: &snapshot.obstacles,
+            });
+        };
+
+        self.updates_since_keyframe += 1;
+
+        let mut added = Vec::new();
+        let mut moved = Vec::new();
+        let mut sent_bolas = HashMap::with_capacity(snapshot.bolas.len());
+        for bola in &snapshot.bolas {
+            let quantized = self.quantize(bola);
+            match self.sent_bolas.remove(&bola.id) {
+                None => added.push(bola),
+                Some(sent) if sent != quantized => moved.push(MovedBola {
+                    id: bola.id,
+                    x: quantized.x,
+                    y: quantized.y,
+                    vx: quantized.vx,
+                    vy: quantized.vy,
+                }),
+                Some(_) => {}
+            }
+
+            sent_bolas.insert(bola.id, quantized);
+        }
+
+        // Whatever's left was sent to the client but is no longer in the arena
+        let removed: Vec<_> = self.sent_bolas.keys().copied().collect();
+        self.sent_bolas = sent_bolas;
+
+        let links = (snapshot.links != last_snapshot.links).then_some(&*snapshot.links);
+        let obstacles =
+            (snapshot.obstacles != last_snapshot.obstacles).then_some(&*snapshot.obstacles);
+
+        if added.is_empty()
+            && removed.is_empty()
+            && moved.is_empty()
+            && links.is_none()
+            && obstacles.is_none()
+        {
+            return None;
+        }
+
+        Some(StateUpdate::Delta {
+            tick: snapshot.tick,
+            time: snapshot.time,
+            added,
+            removed,
+            moved,
+            links,
+            obstacles,
+        })
+    }
+
+    fn quantize(&self, bola: &Bola) -> QuantizedBola {
+        let quantize = |value: f64| (value / self.precision).round() as i64;
+
+        QuantizedBola {
+            x: quantize(bola.center.x),
+            y: quantize(bola.center.y),
+            vx: quantize(bola.velocity.vel_x),
+            vy: quantize(bola.velocity.vel_y),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bola(id: BolaId, x: f64, y: f64) -> Bola {
+        Bola {
+            id,
+            ..Bola::at(x, y, 20.)
+        }
+    }
+
+    fn snapshot(bolas: Vec<Bola>) -> Arc<ArenaSnapshot> {
+        Arc::new(ArenaSnapshot {
+            id: Uuid::nil(),
+            tick: 0,
+            time: 0,
+            bolas,
+            links: Vec::new(),
+            obstacles: Vec::new(),
+            physics: PhysicsParams {
+                timestep_ms: 16,
+                integrator: Default::default(),
+                force_fields: Default::default(),
+                materials: Default::default(),
+                boundaries: Default::default(),
+            },
+        })
+    }
+
+    /// Ids added, removed and moved by a delta, or `None` for anything else
+    fn delta_ids(update: Option<StateUpdate>) -> Option<(Vec<BolaId>, Vec<BolaId>, Vec<BolaId>)> {
+        match update? {
+            StateUpdate::Delta {
+                added,
+                mut removed,
+                moved,
+                ..
+            } => {
+                removed.sort_unstable();
+                Some((
+                    added.iter().map(|b| b.id).collect(),
+                    removed,
+                    moved.iter().map(|m| m.id).collect(),
+                ))
+            }
+            StateUpdate::Keyframe { .. } => None,
+        }
+    }
+
+    #[test]
+    fn sends_keyframe_first_and_every_interval() {
+        let mut tracker = DeltaTracker::new(0.1, 3);
+
+        let keyframes: Vec<_> = (0..7)
+            .map(|i| {
+                let snapshot = snapshot(vec![bola(0, i as f64 * 10., 0.)]);
+                matches!(
+                    tracker.update(&snapshot),
+                    Some(StateUpdate::Keyframe { .. })
+                )
+            })
+            .collect();
+
+        assert_eq!(keyframes, [true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn sends_nothing_when_nothing_changed() {
+        let mut tracker = DeltaTracker::new(0.1, 60);
+        assert!(tracker.update(&snapshot(vec![bola(0, 10., 10.)])).is_some());
+        assert!(tracker.update(&snapshot(vec![bola(0, 10., 10.)])).is_none());
+
+        // Moving by less than the precision isn't a change either
+        assert!(tracker
+            .update(&snapshot(vec![bola(0, 10.01, 10.)]))
+            .is_none());
+    }
+
+    #[test]
+    fn sends_added_and_removed_bolas() {
+        let mut tracker = DeltaTracker::new(0.1, 60);
+        tracker.update(&snapshot(vec![
+            bola(0, 0., 0.),
+            bola(1, 0., 0.),
+            bola(2, 0., 0.),
+        ]));
+
+        let next = snapshot(vec![bola(1, 0., 0.), bola(3, 0., 0.)]);
+        assert_eq!(
+            delta_ids(tracker.update(&next)),
+            Some((vec![3], vec![0, 2], vec![]))
+        );
+
+        // Bolas added in a delta are known to the client from then on
+        let next = snapshot(vec![bola(1, 0., 0.), bola(3, 5., 0.)]);
+        assert_eq!(
+            delta_ids(tracker.update(&next)),
+            Some((vec![], vec![], vec![3]))
+        );
+    }
+
+    #[test]
+    fn bolas_creeping_below_precision_dont_drift() {
+        let mut tracker = DeltaTracker::new(1., 60);
+        tracker.update(&snapshot(vec![bola(0, 0., 0.)]));
+
+        // Each step is under the precision, but the moves add up and are
+        // sent once the bola is closer to the next step than the last one
+        let mut sent_x = 0;
+        for step in 1..=10 {
+            let x = step as f64 * 0.4;
+            let next = snapshot(vec![bola(0, x, 0.)]);
+            if let Some(StateUpdate::Delta { moved, .. }) = tracker.update(&next) {
+                sent_x = moved[0].x;
+            }
+
+            assert!(
+                (sent_x as f64 - x).abs() <= 0.5,
+                "sent {} for {}",
+                sent_x,
+                x
+            );
+        }
+
+        assert_eq!(sent_x, 4);
+    }
+}
@@ -1,3 +1,4 @@
+use actix::Actor;
 use actix_web::{middleware::Logger, web, App, HttpServer};
 use foundations::cli::Cli;
 use foundations::telemetry::init_with_server;
@@ -9,10 +10,18 @@ use std::io;
 use tokio::select;
 use tokio::signal::unix::{signal, SignalKind};
 
+mod arena;
 mod bolas;
 mod collisions;
+mod constraints;
+mod delta;
 mod http;
 mod metrics;
+mod obstacles;
+mod physics;
+mod protocol;
+mod quadtree;
+mod registry;
 mod settings;
 mod static_files;
 mod utils;
@@ -20,6 +29,7 @@ mod version;
 mod websocket;
 
 use self::http::run_http_server;
+use self::registry::ArenaRegistry;
 use self::settings::{BolasConfig, BolasSettings, ServerListenerSettings};
 use self::utils::bootstrap_to_io_error;
 use self::version::VersionInfo;
@@ -30,11 +40,13 @@ async fn run_application_server(
     version_info: VersionInfo,
 ) -> io::Result<()> {
     server_listener_settings.validate("application")?;
+    let arena_registry = ArenaRegistry::new(bolas_config.clone()).start();
 
     let app_server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(bolas_config.clone()))
             .app_data(web::Data::new(version_info.clone()))
+            .app_data(web::Data::new(arena_registry.clone()))
             .wrap(Logger::default())
             .route("/ws", web::get().to(websocket::serve_websockets))
             .route("/", web::get().to(static_files::serve_index_html))
@@ -13,4 +13,9 @@ pub(crate) mod metrics {
 
     /// Number of active bolas within all active arenas
     pub(crate) fn bolas_active() -> Gauge;
+
+    /// Number of colliding pairs on which the verified collision detection
+    /// algorithm disagreed with brute force, by whether the algorithm
+    /// missed the pair or reported a pair that isn't colliding
+    pub(crate) fn collision_detection_mismatches_total(kind: &'static str) -> Counter;
 }
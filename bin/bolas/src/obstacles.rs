@@ -0,0 +1,237 @@
+use crate::bolas::Bola;
+use crate::protocol::serialize_coordinate;
+use crate::quadtree::Aabb;
+use foundations::settings::settings;
+use serde::{Deserialize, Serialize};
+
+/// Static obstacles every new arena starts out with, clients may add more
+/// of their own
+#[settings]
+pub(crate) struct ArenaLayout {
+    /// Straight walls between a pair of points
+    pub(crate) segments: Vec<Segment>,
+
+    /// Solid axis-aligned blocks
+    pub(crate) rectangles: Vec<Rectangle>,
+
+    /// Solid circular pegs
+    pub(crate) pegs: Vec<Peg>,
+}
+
+impl ArenaLayout {
+    pub(crate) fn obstacles(&self) -> impl Iterator<Item = Obstacle> + '_ {
+        self.segments
+            .iter()
+            .copied()
+            .map(Obstacle::Segment)
+            .chain(self.rectangles.iter().copied().map(Obstacle::Rectangle))
+            .chain(self.pegs.iter().copied().map(Obstacle::Peg))
+    }
+}
+
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Segment {
+    /// Horizontal position of the start of the segment on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) x1: f64,
+
+    /// Vertical position of the start of the segment on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) y1: f64,
+
+    /// Horizontal position of the end of the segment on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) x2: f64,
+
+    /// Vertical position of the end of the segment on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) y2: f64,
+}
+
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Rectangle {
+    /// Horizontal position of the left edge of the rectangle on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) x: f64,
+
+    /// Vertical position of the top edge of the rectangle on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) y: f64,
+
+    /// Width of the rectangle in pixels
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) width: f64,
+
+    /// Height of the rectangle in pixels
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) height: f64,
+}
+
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Peg {
+    /// Horizontal position of the center of the peg on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) x: f64,
+
+    /// Vertical position of the center of the peg on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) y: f64,
+
+    /// Radius of the peg in pixels
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) radius: f64,
+}
+
+/// Where a bola is touching an obstacle, with the direction pointing out of
+/// the obstacle towards the bola's center and how far the bola has sunk into
+/// the obstacle along it
+pub(crate) struct Contact {
+    pub(crate) normal: (f64, f64),
+    pub(crate) penetration: f64,
+}
+
+/// Something static inside the arena which bolas bounce off of, sent to
+/// clients so they can draw it, tagged with its kind
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "k", rename_all = "snake_case")]
+pub(crate) enum Obstacle {
+    Segment(Segment),
+    Rectangle(Rectangle),
+    Peg(Peg),
+}
+
+impl Obstacle {
+    /// Returns `None` if any of the obstacle's coordinates aren't finite,
+    /// otherwise the obstacle with a rectangle of negative size flipped to
+    /// cover the same area, and a peg's radius made non-negative
+    pub(crate) fn normalized(self) -> Option<Self> {
+        let obstacle = match self {
+            Self::Segment(s) => Self::Segment(s),
+            Self::Rectangle(r) => Self::Rectangle(Rectangle {
+                x: r.x.min(r.x + r.width),
+                y: r.y.min(r.y + r.height),
+                width: r.width.abs(),
+                height: r.height.abs(),
+            }),
+            Self::Peg(p) => Self::Peg(Peg {
+                radius: p.radius.abs(),
+                ..p
+            }),
+        };
+
+        let bounds = obstacle.bounds();
+        [bounds.min_x, bounds.min_y, bounds.max_x, bounds.max_y]
+            .iter()
+            .all(|c| c.is_finite())
+            .then_some(obstacle)
+    }
+
+    pub(crate) fn bounds(&self) -> Aabb {
+        match self {
+            Self::Segment(s) => Aabb {
+                min_x: s.x1.min(s.x2),
+                min_y: s.y1.min(s.y2),
+                max_x: s.x1.max(s.x2),
+                max_y: s.y1.max(s.y2),
+            },
+            Self::Rectangle(r) => Aabb {
+                min_x: r.x,
+                min_y: r.y,
+                max_x: r.x + r.width,
+                max_y: r.y + r.height,
+            },
+            Self::Peg(p) => Aabb {
+                min_x: p.x - p.radius,
+                min_y: p.y - p.radius,
+                max_x: p.x + p.radius,
+                max_y: p.y + p.radius,
+            },
+        }
+    }
+
+    /// Narrow phase check of whether a bola is touching the obstacle,
+    /// returning where if it is
+    pub(crate) fn contact(&self, bola: &Bola) -> Option<Contact> {
+        let (x, y) = (bola.center.x, bola.center.y);
+
+        match self {
+            Self::Peg(p) => contact_with_point(x, y, bola.radius + p.radius, (p.x, p.y), (0., -1.)),
+            Self::Segment(s) => {
+                let direction = (s.x2 - s.x1, s.y2 - s.y1);
+                let length_squared = direction.0.powf(2.) + direction.1.powf(2.);
+                let t = if length_squared == 0. {
+                    0.
+                } else {
+                    (((x - s.x1) * direction.0 + (y - s.y1) * direction.1) / length_squared)
+                        .clamp(0., 1.)
+                };
+                let closest = (s.x1 + direction.0 * t, s.y1 + direction.1 * t);
+
+                // A bola centered right on the segment is pushed out of
+                // whichever side the segment's normal points to
+                let length = length_squared.sqrt();
+                let fallback = if length == 0. {
+                    (0., -1.)
+                } else {
+                    (-direction.1 / length, direction.0 / length)
+                };
+
+                contact_with_point(x, y, bola.radius, closest, fallback)
+            }
+            Self::Rectangle(r) => {
+                let (max_x, max_y) = (r.x + r.width, r.y + r.height);
+                let inside = r.x < x && x < max_x && r.y < y && y < max_y;
+                if !inside {
+                    let closest = (x.clamp(r.x, max_x), y.clamp(r.y, max_y));
+                    return contact_with_point(x, y, bola.radius, closest, (0., -1.));
+                }
+
+                // A bola whose center has sunk inside the rectangle is pushed
+                // out through the nearest face
+                [
+                    (x - r.x, (-1., 0.)),
+                    (max_x - x, (1., 0.)),
+                    (y - r.y, (0., -1.)),
+                    (max_y - y, (0., 1.)),
+                ]
+                .iter()
+                .min_by(|(a, _), (b, _)| a.total_cmp(b))
+                .map(|&(depth, normal)| Contact {
+                    normal,
+                    penetration: bola.radius + depth,
+                })
+            }
+        }
+    }
+}
+
+/// Contact between a bola centered at `(x, y)` and the closest point on an
+/// obstacle to it, if they're nearer than `contact_distance`. The fallback
+/// normal is used when the bola is centered exactly on the point.
+fn contact_with_point(
+    x: f64,
+    y: f64,
+    contact_distance: f64,
+    point: (f64, f64),
+    fallback_normal: (f64, f64),
+) -> Option<Contact> {
+    let offset = (x - point.0, y - point.1);
+    let distance = (offset.0.powf(2.) + offset.1.powf(2.)).sqrt();
+    if distance >= contact_distance {
+        return None;
+    }
+
+    let normal = if distance == 0. {
+        fallback_normal
+    } else {
+        (offset.0 / distance, offset.1 / distance)
+    };
+
+    Some(Contact {
+        normal,
+        penetration: contact_distance - distance,
+    })
+}
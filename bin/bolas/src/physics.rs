@@ -0,0 +1,413 @@
+use crate::bolas::{Bola, Point};
+use crate::protocol::serialize_coordinate;
+use foundations::settings::settings;
+use serde::Serialize;
+
+/// Attractors closer than this are treated as being this far away, so a
+/// bola passing right over one isn't flung off at an absurd speed
+const MIN_ATTRACTOR_DISTANCE: f64 = 20.;
+
+/// Most attractors an arena may have, every one of them is evaluated for
+/// every bola on every substep
+const MAX_ATTRACTORS: usize = 16;
+
+/// Bounds on the gravity, in pixels per second squared, and attractor
+/// strength an arena may have, beyond which bolas would be flung to
+/// infinity within a few steps
+const MAX_GRAVITY: f64 = 10000.;
+const MAX_ATTRACTOR_STRENGTH: f64 = 1e7;
+
+/// Numerical method used to advance the position and velocity of each bola
+/// under the forces acting on it
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) enum Integrator {
+    /// Moves bolas along their velocity from the start of the step, then
+    /// accelerates them. Cheapest, but gains energy under constant forces.
+    ExplicitEuler,
+    /// Accelerates bolas, then moves them along their new velocity. As cheap
+    /// as explicit Euler, but stable under gravity.
+    #[default]
+    SemiImplicitEuler,
+    /// Moves bolas using their acceleration at the start of the step, then
+    /// averages it with the acceleration at their new position. Stable and
+    /// accurate for position dependent forces like attractors.
+    VelocityVerlet,
+    /// Fourth order Runge-Kutta, the most accurate and most expensive,
+    /// evaluating the forces four times per step
+    Rk4,
+}
+
+impl Integrator {
+    /// Advances the bola's position and velocity by `dt` seconds under the
+    /// arena's force fields
+    pub(crate) fn integrate(self, bola: &mut Bola, force_fields: &ForceFields, dt: f64) {
+        let acceleration_at = |x: f64, y: f64| force_fields.acceleration_at(&Point { x, y });
+        let (x, y) = (bola.center.x, bola.center.y);
+        let (vel_x, vel_y) = (bola.velocity.vel_x, bola.velocity.vel_y);
+
+        let ((new_x, new_y), (new_vel_x, new_vel_y)) = match self {
+            Self::ExplicitEuler => {
+                let a = acceleration_at(x, y);
+                (
+                    (x + vel_x * dt, y + vel_y * dt),
+                    (vel_x + a.0 * dt, vel_y + a.1 * dt),
+                )
+            }
+            Self::SemiImplicitEuler => {
+                let a = acceleration_at(x, y);
+                let v = (vel_x + a.0 * dt, vel_y + a.1 * dt);
+                ((x + v.0 * dt, y + v.1 * dt), v)
+            }
+            Self::VelocityVerlet => {
+                let a = acceleration_at(x, y);
+                let p = (
+                    x + vel_x * dt + 0.5 * a.0 * dt.powf(2.),
+                    y + vel_y * dt + 0.5 * a.1 * dt.powf(2.),
+                );
+                let new_a = acceleration_at(p.0, p.1);
+                let v = (
+                    vel_x + 0.5 * (a.0 + new_a.0) * dt,
+                    vel_y + 0.5 * (a.1 + new_a.1) * dt,
+                );
+                (p, v)
+            }
+            Self::Rk4 => {
+                // Each k is the derivative of (position, velocity), which is
+                // (velocity, acceleration), at a trial point within the step
+                let k1 = ((vel_x, vel_y), acceleration_at(x, y));
+                let k2 = (
+                    (vel_x + k1.1 .0 * dt / 2., vel_y + k1.1 .1 * dt / 2.),
+                    acceleration_at(x + k1.0 .0 * dt / 2., y + k1.0 .1 * dt / 2.),
+                );
+                let k3 = (
+                    (vel_x + k2.1 .0 * dt / 2., vel_y + k2.1 .1 * dt / 2.),
+                    acceleration_at(x + k2.0 .0 * dt / 2., y + k2.0 .1 * dt / 2.),
+                );
+                let k4 = (
+                    (vel_x + k3.1 .0 * dt, vel_y + k3.1 .1 * dt),
+                    acceleration_at(x + k3.0 .0 * dt, y + k3.0 .1 * dt),
+                );
+                let weighted =
+                    |k1: f64, k2: f64, k3: f64, k4: f64| (k1 + 2. * k2 + 2. * k3 + k4) / 6.;
+
+                (
+                    (
+                        x + weighted(k1.0 .0, k2.0 .0, k3.0 .0, k4.0 .0) * dt,
+                        y + weighted(k1.0 .1, k2.0 .1, k3.0 .1, k4.0 .1) * dt,
+                    ),
+                    (
+                        vel_x + weighted(k1.1 .0, k2.1 .0, k3.1 .0, k4.1 .0) * dt,
+                        vel_y + weighted(k1.1 .1, k2.1 .1, k3.1 .1, k4.1 .1) * dt,
+                    ),
+                )
+            }
+        };
+
+        bola.center.x = new_x;
+        bola.center.y = new_y;
+        bola.velocity.vel_x = new_vel_x;
+        bola.velocity.vel_y = new_vel_y;
+    }
+}
+
+/// Forces acting on every bola in an arena
+#[settings]
+#[derive(PartialEq)]
+pub(crate) struct ForceFields {
+    /// Uniform gravity pulling every bola in the same direction
+    pub(crate) gravity: Gravity,
+
+    /// Fixed points pulling bolas towards, or pushing them away from, them
+    pub(crate) attractors: Vec<Attractor>,
+}
+
+/// Parameters an arena is simulated with, sent to clients so they can
+/// predict how bolas move between state updates
+#[derive(Clone, PartialEq, Serialize)]
+pub(crate) struct PhysicsParams {
+    /// Length in milliseconds of each step of the simulation
+    pub(crate) timestep_ms: u64,
+    pub(crate) integrator: Integrator,
+    pub(crate) force_fields: ForceFields,
+    pub(crate) materials: Materials,
+    pub(crate) boundaries: Boundaries,
+}
+
+/// What happens to bolas reaching an edge of the arena
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) enum BoundaryMode {
+    /// Bolas bounce back off the edge
+    #[default]
+    Reflect,
+    /// Bolas leaving through the edge come back in through the opposite one
+    Wrap,
+    /// Bolas leaving through the edge are removed from the arena
+    Open,
+    /// Bolas reaching the edge stop dead against it
+    Sticky,
+}
+
+/// Boundary mode of each edge of the arena
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Boundaries {
+    /// Top edge of the canvas
+    pub(crate) top: BoundaryMode,
+
+    /// Bottom edge of the canvas, setting this to `open` along with gravity
+    /// pulling downwards drains bolas out of the arena
+    pub(crate) bottom: BoundaryMode,
+
+    /// Left edge of the canvas
+    pub(crate) left: BoundaryMode,
+
+    /// Right edge of the canvas
+    pub(crate) right: BoundaryMode,
+}
+
+/// How bolas in an arena lose energy as they move and collide. Bolas may
+/// override these with values of their own.
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Materials {
+    /// Linear air drag per second, a bola's velocity decays by a factor of
+    /// e to the power of the drag every second
+    pub(crate) drag: f64,
+
+    /// Deceleration in pixels per second squared opposing the motion of
+    /// every moving bola, until it comes to a stop
+    pub(crate) rolling_friction: f64,
+
+    /// Coefficient of restitution for bounces off the edges of the arena,
+    /// from 1 for perfectly elastic bounces to 0 for fully inelastic ones
+    #[serde(default = "default_restitution")]
+    pub(crate) wall_restitution: f64,
+
+    /// Coefficient of restitution for collisions between bolas, from 1 for
+    /// perfectly elastic collisions to 0 for fully inelastic ones
+    #[serde(default = "default_restitution")]
+    pub(crate) restitution: f64,
+}
+
+fn default_restitution() -> f64 {
+    1.
+}
+
+impl Materials {
+    /// Clamps each coefficient to the range in which it makes sense
+    pub(crate) fn normalized(self) -> Self {
+        Self {
+            drag: normalize_coefficient(self.drag, 0., f64::MAX, 0.),
+            rolling_friction: normalize_coefficient(self.rolling_friction, 0., f64::MAX, 0.),
+            wall_restitution: normalize_coefficient(self.wall_restitution, 0., 1., 1.),
+            restitution: normalize_coefficient(self.restitution, 0., 1., 1.),
+        }
+    }
+}
+
+pub(crate) fn normalize_coefficient(value: f64, min: f64, max: f64, default: f64) -> f64 {
+    if value.is_nan() {
+        default
+    } else {
+        value.clamp(min, max)
+    }
+}
+
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Gravity {
+    /// Direction gravity pulls in, in degrees clockwise from the positive
+    /// x axis, so 90 pulls towards the bottom of the canvas
+    pub(crate) direction: f64,
+
+    /// Acceleration due to gravity in pixels per second squared
+    pub(crate) magnitude: f64,
+}
+
+#[settings]
+#[derive(Copy, PartialEq)]
+pub(crate) struct Attractor {
+    /// Horizontal position of the attractor on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) x: f64,
+
+    /// Vertical position of the attractor on the canvas
+    #[serde(serialize_with = "serialize_coordinate")]
+    pub(crate) y: f64,
+
+    /// Strength of the attractor, bolas are accelerated towards it at this
+    /// value divided by the square of their distance from it in pixels per
+    /// second squared. Negative strengths push bolas away instead.
+    pub(crate) strength: f64,
+}
+
+impl ForceFields {
+    /// Clamps gravity and the strength of each attractor to the range in
+    /// which they make sense, drops attractors whose position isn't a finite
+    /// number and any beyond the most an arena may have
+    pub(crate) fn normalized(self) -> Self {
+        let direction = if self.gravity.direction.is_finite() {
+            self.gravity.direction.rem_euclid(360.)
+        } else {
+            0.
+        };
+
+        Self {
+            gravity: Gravity {
+                direction,
+                magnitude: normalize_coefficient(
+                    self.gravity.magnitude,
+                    -MAX_GRAVITY,
+                    MAX_GRAVITY,
+                    0.,
+                ),
+            },
+            attractors: self
+                .attractors
+                .into_iter()
+                .filter(|a| a.x.is_finite() && a.y.is_finite())
+                .map(|a| Attractor {
+                    strength: normalize_coefficient(
+                        a.strength,
+                        -MAX_ATTRACTOR_STRENGTH,
+                        MAX_ATTRACTOR_STRENGTH,
+                        0.,
+                    ),
+                    ..a
+                })
+                .take(MAX_ATTRACTORS)
+                .collect(),
+        }
+    }
+
+    /// Returns the acceleration, in pixels per second squared, that the
+    /// force fields exert on a bola at the given point
+    pub(crate) fn acceleration_at(&self, point: &Point) -> (f64, f64) {
+        let direction = self.gravity.direction.to_radians();
+        let mut acceleration = (
+            self.gravity.magnitude * direction.cos(),
+            self.gravity.magnitude * direction.sin(),
+        );
+
+        for attractor in &self.attractors {
+            let offset = (attractor.x - point.x, attractor.y - point.y);
+            let distance = (offset.0.powf(2.) + offset.1.powf(2.)).sqrt();
+            if distance == 0. {
+                continue;
+            }
+
+            let magnitude = attractor.strength / distance.max(MIN_ATTRACTOR_DISTANCE).powf(2.);
+            acceleration.0 += magnitude * offset.0 / distance;
+            acceleration.1 += magnitude * offset.1 / distance;
+        }
+
+        acceleration
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moving_bola(x: f64, y: f64, vel_x: f64, vel_y: f64) -> Bola {
+        let mut bola = Bola::at(x, y, 20.);
+        bola.velocity.vel_x = vel_x;
+        bola.velocity.vel_y = vel_y;
+        bola
+    }
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64), integrator: Integrator) {
+        assert!(
+            (actual.0 - expected.0).abs() < 1e-9 && (actual.1 - expected.1).abs() < 1e-9,
+            "{:?} gave {:?}, expected {:?}",
+            integrator,
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn single_steps_under_gravity() {
+        let (g, dt): (f64, f64) = (100., 0.1);
+        let force_fields = ForceFields {
+            gravity: Gravity {
+                direction: 90.,
+                magnitude: g,
+            },
+            attractors: Vec::new(),
+        };
+
+        // Constant acceleration makes the exact path a parabola, which is
+        // what the second order and higher integrators trace out
+        let exact = (
+            (1., 2. - 20. * dt + 0.5 * g * dt.powf(2.)),
+            (10., -20. + g * dt),
+        );
+        let semi_implicit = ((1., 2. + (-20. + g * dt) * dt), (10., -20. + g * dt));
+        let explicit = ((1., 2. - 20. * dt), (10., -20. + g * dt));
+
+        for &(integrator, (position, velocity)) in &[
+            (Integrator::ExplicitEuler, explicit),
+            (Integrator::SemiImplicitEuler, semi_implicit),
+            (Integrator::VelocityVerlet, exact),
+            (Integrator::Rk4, exact),
+        ] {
+            let mut bola = moving_bola(0., 2., 10., -20.);
+            integrator.integrate(&mut bola, &force_fields, dt);
+
+            // The bola also moves 1px to the right along its velocity
+            assert_close((bola.center.x, bola.center.y), position, integrator);
+            assert_close(
+                (bola.velocity.vel_x, bola.velocity.vel_y),
+                velocity,
+                integrator,
+            );
+        }
+    }
+
+    /// Runs a bola once around a circular orbit of an attractor, only
+    /// explicit Euler should spiral outwards, gaining energy as it goes
+    #[test]
+    fn only_explicit_euler_gains_energy_in_orbit() {
+        let (strength, radius, dt): (f64, f64, f64) = (1e6, 100., 0.01);
+        let force_fields = ForceFields {
+            gravity: Gravity {
+                direction: 0.,
+                magnitude: 0.,
+            },
+            attractors: vec![Attractor {
+                x: 0.,
+                y: 0.,
+                strength,
+            }],
+        };
+        let energy = |bola: &Bola| {
+            let distance = (bola.center.x.powf(2.) + bola.center.y.powf(2.)).sqrt();
+            (bola.velocity.vel_x.powf(2.) + bola.velocity.vel_y.powf(2.)) / 2. - strength / distance
+        };
+
+        let speed = (strength / radius).sqrt();
+        let period = 2. * std::f64::consts::PI * radius / speed;
+        for &integrator in &[
+            Integrator::ExplicitEuler,
+            Integrator::SemiImplicitEuler,
+            Integrator::VelocityVerlet,
+            Integrator::Rk4,
+        ] {
+            let mut bola = moving_bola(radius, 0., 0., speed);
+            let initial_energy = energy(&bola);
+            for _ in 0..(period / dt) as usize {
+                integrator.integrate(&mut bola, &force_fields, dt);
+            }
+
+            let gain = (energy(&bola) - initial_energy) / initial_energy.abs();
+            if integrator == Integrator::ExplicitEuler {
+                assert!(gain > 0.05, "{:?} gained {}", integrator, gain);
+            } else {
+                assert!(gain.abs() < 0.001, "{:?} gained {}", integrator, gain);
+            }
+        }
+    }
+}
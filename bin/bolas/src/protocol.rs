@@ -0,0 +1,196 @@
+use actix_web::{http::header, web::Bytes, HttpRequest};
+use serde::{
+    de::{self, DeserializeOwned},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::io;
+use uuid::Uuid;
+
+use crate::{delta::StateUpdate, physics::PhysicsParams};
+
+/// Version of the messages exchanged with clients, bumped whenever a change
+/// would break clients written against the previous version
+pub(crate) const PROTOCOL_VERSION: u32 = 1;
+
+/// Identifier a client may give a message it sends, so it can match any
+/// error reply to the message which caused it
+pub(crate) type MessageId = u64;
+
+/// Picks just the id out of a client message, ignoring the rest of it
+#[derive(Deserialize)]
+pub(crate) struct MessageIdProbe {
+    #[serde(default)]
+    pub(crate) id: Option<MessageId>,
+}
+
+/// Encoding of the messages exchanged with a client, negotiated through the
+/// websocket subprotocol header when the client connects
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) enum Encoding {
+    /// JSON sent in text frames, used when the client doesn't ask for a
+    /// subprotocol
+    #[default]
+    Json,
+    /// MessagePack sent in binary frames, with coordinates sent as f32,
+    /// which is far more compact for large arenas
+    MessagePack,
+}
+
+impl Encoding {
+    pub(crate) const ALL: [Encoding; 2] = [Encoding::Json, Encoding::MessagePack];
+
+    pub(crate) fn subprotocol(self) -> &'static str {
+        match self {
+            Self::Json => "bolas.json",
+            Self::MessagePack => "bolas.msgpack",
+        }
+    }
+
+    /// Picks the first of the subprotocols the client asked for which the
+    /// server supports, or `None` if it didn't ask for any supported one
+    pub(crate) fn negotiate(req: &HttpRequest) -> Option<Self> {
+        let requested = req
+            .headers()
+            .get(header::SEC_WEBSOCKET_PROTOCOL)?
+            .to_str()
+            .ok()?;
+
+        requested.split(',').map(str::trim).find_map(|protocol| {
+            Self::ALL
+                .iter()
+                .copied()
+                .find(|e| e.subprotocol() == protocol)
+        })
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(self, message: &[u8]) -> io::Result<T> {
+        match self {
+            Self::Json => serde_json::from_slice(message)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Self::MessagePack => rmp_serde::from_slice(message)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    pub(crate) fn encode<T: Serialize>(self, value: &T) -> io::Result<EncodedMessage> {
+        match self {
+            Self::Json => serde_json::to_string(value)
+                .map(EncodedMessage::Text)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Self::MessagePack => rmp_serde::to_vec_named(value)
+                .map(|m| EncodedMessage::Binary(m.into()))
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+/// Every message the server sends to a client, tagged with its type
+#[derive(Serialize)]
+#[serde(tag = "t", rename_all = "snake_case")]
+pub(crate) enum ServerMessage<'a> {
+    /// First message sent to a client once it has joined an arena, telling
+    /// it what it's talking to before any state is sent
+    Hello {
+        protocol: u32,
+        arena: Uuid,
+
+        /// Version of the server, as served from `/server-version.json`
+        server: &'a str,
+        physics: &'a PhysicsParams,
+    },
+    State(StateUpdate<'a>),
+    Error {
+        code: ErrorCode,
+
+        /// Id of the message which caused the error, if the client gave it
+        /// one and it could be read
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<MessageId>,
+
+        /// Human readable description of what went wrong, for debugging
+        /// rather than for showing to users
+        message: String,
+    },
+    Event(ArenaEvent<'a>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ErrorCode {
+    /// The message couldn't be parsed as a client message
+    InvalidMessage,
+    /// The message was sent in a kind of websocket frame which doesn't
+    /// carry client messages
+    UnsupportedFrame,
+    /// The message could be parsed, but a value in it is out of range or
+    /// doesn't make sense, like a spring between a bola and itself
+    InvalidArgument,
+    /// The message refers to a bola which doesn't exist, or no longer does
+    UnknownBola,
+    /// The arena already holds as many of what the message adds as it may
+    LimitReached,
+    /// The client isn't allowed to do what the message asks, like dragging
+    /// a bola another client is holding
+    NotPermitted,
+}
+
+/// Something that happened in the arena which isn't part of its state
+#[derive(Serialize)]
+#[serde(tag = "e", rename_all = "snake_case")]
+pub(crate) enum ArenaEvent<'a> {
+    /// The client which owns the arena changed the physics it's
+    /// simulated with
+    PhysicsChanged { physics: &'a PhysicsParams },
+}
+
+/// A message encoded for sending to a client, as a text frame for JSON and
+/// a binary frame otherwise
+pub(crate) enum EncodedMessage {
+    Text(String),
+    Binary(Bytes),
+}
+
+/// Serializes a coordinate as an f64 in human readable encodings like JSON,
+/// and as an f32 in binary ones, where the extra precision is wasted on
+/// positions in pixels
+pub(crate) fn serialize_coordinate<S: Serializer>(
+    value: &f64,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if serializer.is_human_readable() {
+        serializer.serialize_f64(*value)
+    } else {
+        serializer.serialize_f32(*value as f32)
+    }
+}
+
+/// Deserializes a coordinate, rejecting NaN and infinities, which binary
+/// encodings like MessagePack can carry even though JSON can't
+pub(crate) fn deserialize_coordinate<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    let value = f64::deserialize(deserializer)?;
+    if !value.is_finite() {
+        return Err(de::Error::custom("coordinate must be a finite number"));
+    }
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bolas::Point;
+
+    #[test]
+    fn rejects_non_finite_coordinates() {
+        for &x in &[f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let message = rmp_serde::to_vec_named(&Point { x, y: 0. }).unwrap();
+            assert!(Encoding::MessagePack.decode::<Point>(&message).is_err());
+        }
+
+        let message = rmp_serde::to_vec_named(&Point { x: 1.5, y: 0. }).unwrap();
+        let point: Point = Encoding::MessagePack.decode(&message).unwrap();
+        assert_eq!(point, Point { x: 1.5, y: 0. });
+    }
+}
@@ -0,0 +1,223 @@
+/// Number of entries a node holds before it's split into quadrants
+const NODE_CAPACITY: usize = 8;
+
+/// Maximum depth of the tree, beyond which nodes are never split, which
+/// bounds the work done when many bolas are stacked on the same point
+const MAX_DEPTH: usize = 10;
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Aabb {
+    pub(crate) min_x: f64,
+    pub(crate) min_y: f64,
+    pub(crate) max_x: f64,
+    pub(crate) max_y: f64,
+}
+
+impl Aabb {
+    pub(crate) fn overlaps(&self, other: &Aabb) -> bool {
+        self.min_x < other.max_x
+            && other.min_x < self.max_x
+            && self.min_y < other.max_y
+            && other.min_y < self.max_y
+    }
+
+    fn contains(&self, other: &Aabb) -> bool {
+        self.min_x <= other.min_x
+            && other.max_x <= self.max_x
+            && self.min_y <= other.min_y
+            && other.max_y <= self.max_y
+    }
+
+    fn union(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min_x: self.min_x.min(other.min_x),
+            min_y: self.min_y.min(other.min_y),
+            max_x: self.max_x.max(other.max_x),
+            max_y: self.max_y.max(other.max_y),
+        }
+    }
+
+    fn quadrants(&self) -> [Aabb; 4] {
+        let mid_x = (self.min_x + self.max_x) / 2.;
+        let mid_y = (self.min_y + self.max_y) / 2.;
+
+        [
+            Aabb {
+                max_x: mid_x,
+                max_y: mid_y,
+                ..*self
+            },
+            Aabb {
+                min_x: mid_x,
+                max_y: mid_y,
+                ..*self
+            },
+            Aabb {
+                max_x: mid_x,
+                min_y: mid_y,
+                ..*self
+            },
+            Aabb {
+                min_x: mid_x,
+                min_y: mid_y,
+                ..*self
+            },
+        ]
+    }
+}
+
+/// Region quadtree over axis-aligned bounding boxes. Nodes split into
+/// quadrants as they fill up, so densely populated regions end up finely
+/// subdivided while sparse ones stay coarse. Entries straddling a split
+/// stay in the smallest node that fully contains them.
+pub(crate) struct Quadtree {
+    root: QuadtreeNode,
+}
+
+struct QuadtreeNode {
+    bounds: Aabb,
+    depth: usize,
+    entries: Vec<(Aabb, usize)>,
+    children: Option<Box<[QuadtreeNode; 4]>>,
+}
+
+impl Quadtree {
+    /// Creates a tree covering the union of the given boxes
+    pub(crate) fn covering(boxes: impl Iterator<Item = Aabb>) -> Self {
+        let bounds = boxes.reduce(|a, b| a.union(&b)).unwrap_or(Aabb {
+            min_x: 0.,
+            min_y: 0.,
+            max_x: 0.,
+            max_y: 0.,
+        });
+
+        Self {
+            root: QuadtreeNode::new(bounds, 0),
+        }
+    }
+
+    pub(crate) fn insert(&mut self, bounds: Aabb, data: usize) {
+        self.root.insert(bounds, data);
+    }
+
+    /// Returns the data of every entry whose box overlaps the given one
+    pub(crate) fn find(&self, bounds: &Aabb) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.root.find(bounds, &mut found);
+        found
+    }
+}
+
+impl QuadtreeNode {
+    fn new(bounds: Aabb, depth: usize) -> Self {
+        Self {
+            bounds,
+            depth,
+            entries: Vec::new(),
+            children: None,
+        }
+    }
+
+    fn insert(&mut self, bounds: Aabb, data: usize) {
+        if let Some(child) = self.child_containing(&bounds) {
+            child.insert(bounds, data);
+            return;
+        }
+
+        self.entries.push((bounds, data));
+        if self.children.is_none() && self.entries.len() > NODE_CAPACITY && self.depth < MAX_DEPTH {
+            self.split();
+        }
+    }
+
+    fn split(&mut self) {
+        let [q1, q2, q3, q4] = self.bounds.quadrants();
+        let depth = self.depth + 1;
+        self.children = Some(Box::new([
+            QuadtreeNode::new(q1, depth),
+            QuadtreeNode::new(q2, depth),
+            QuadtreeNode::new(q3, depth),
+            QuadtreeNode::new(q4, depth),
+        ]));
+
+        for (bounds, data) in std::mem::take(&mut self.entries) {
+            self.insert(bounds, data);
+        }
+    }
+
+    fn child_containing(&mut self, bounds: &Aabb) -> Option<&mut QuadtreeNode> {
+        self.children
+            .as_mut()?
+            .iter_mut()
+            .find(|c| c.bounds.contains(bounds))
+    }
+
+    fn find(&self, bounds: &Aabb, found: &mut Vec<usize>) {
+        found.extend(
+            self.entries
+                .iter()
+                .filter(|(b, _)| b.overlaps(bounds))
+                .map(|(_, data)| *data),
+        );
+
+        for child in self.children.iter().flat_map(|c| c.iter()) {
+            if child.bounds.overlaps(bounds) {
+                child.find(bounds, found);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aabb(x: f64, y: f64, half_size: f64) -> Aabb {
+        Aabb {
+            min_x: x - half_size,
+            min_y: y - half_size,
+            max_x: x + half_size,
+            max_y: y + half_size,
+        }
+    }
+
+    #[test]
+    fn finds_same_boxes_as_brute_force() {
+        // A grid dense enough to split several levels deep, whose larger
+        // boxes straddle the quadrant boundaries, plus a pile of boxes on
+        // the same point which can never be split apart
+        let mut boxes = Vec::new();
+        for i in 0..20 {
+            for j in 0..20 {
+                let half_size = if (i + j) % 5 == 0 { 30. } else { 2. };
+                boxes.push(aabb(i as f64 * 50., j as f64 * 50., half_size));
+            }
+        }
+        boxes.extend((0..50).map(|_| aabb(500., 500., 1.)));
+
+        let mut tree = Quadtree::covering(boxes.iter().copied());
+        for (data, bounds) in boxes.iter().enumerate() {
+            tree.insert(*bounds, data);
+        }
+        assert!(tree.root.children.is_some());
+
+        // Boxes added after the tree was sized can fall outside its bounds
+        for &bounds in &[aabb(-100., 500., 20.), aabb(1200., 1200., 300.)] {
+            tree.insert(bounds, boxes.len());
+            boxes.push(bounds);
+        }
+
+        for query in boxes
+            .iter()
+            .chain(&[aabb(0., 0., 2000.), aabb(-500., -500., 10.)])
+        {
+            let mut found = tree.find(query);
+            found.sort_unstable();
+
+            let expected: Vec<_> = (0..boxes.len())
+                .filter(|data| boxes[*data].overlaps(query))
+                .collect();
+            assert_eq!(found, expected, "{:?}", query);
+        }
+    }
+}
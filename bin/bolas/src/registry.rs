@@ -0,0 +1,167 @@
+use actix::{
+    Actor, ActorFutureExt, Addr, Arbiter, ArbiterHandle, Context, Handler, MailboxError, Message,
+    Recipient, ResponseActFuture, WrapFuture,
+};
+use foundations::telemetry::log;
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::{
+    arena::{ArenaActor, ArenaStateUpdate, CommandRejected, Connect, Disconnect},
+    bolas::BolasArena,
+    physics::PhysicsParams,
+    settings::BolasConfig,
+};
+
+#[derive(Clone)]
+pub(crate) struct ArenaHandle {
+    pub(crate) id: Uuid,
+    pub(crate) addr: Addr<ArenaActor>,
+}
+
+/// Joins the arena with the given id, creating it if it doesn't exist
+/// yet. A new arena with a random id is created if no id is given. Replies
+/// once the arena has added the client, with the physics it's simulated
+/// with.
+#[derive(Message)]
+#[rtype(result = "Result<(ArenaHandle, PhysicsParams), MailboxError>")]
+pub(crate) struct JoinArena {
+    pub(crate) arena_id: Option<Uuid>,
+    pub(crate) client_id: Uuid,
+    pub(crate) recipient: Recipient<ArenaStateUpdate>,
+    pub(crate) rejections: Recipient<CommandRejected>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub(crate) struct LeaveArena {
+    pub(crate) arena_id: Uuid,
+    pub(crate) client_id: Uuid,
+}
+
+struct RegisteredArena {
+    addr: Addr<ArenaActor>,
+    clients: usize,
+}
+
+/// Server-wide registry of running arenas. All joins and leaves go through
+/// the registry so that an arena is never handed out after its last client
+/// has left and it has been told to shut down.
+pub(crate) struct ArenaRegistry {
+    config: BolasConfig,
+    arenas: HashMap<Uuid, RegisteredArena>,
+
+    /// Pool of threads arenas are spread across, so that a busy arena only
+    /// holds up the arenas sharing its thread rather than every arena on
+    /// the server. Arenas are handed out to them in turn.
+    arbiters: Vec<ArbiterHandle>,
+    next_arbiter: usize,
+}
+
+impl ArenaRegistry {
+    pub(crate) fn new(config: BolasConfig) -> Self {
+        Self {
+            config,
+            arenas: Default::default(),
+            arbiters: Vec::new(),
+            next_arbiter: 0,
+        }
+    }
+
+    fn create_arena(&mut self, arena_id: Uuid) -> RegisteredArena {
+        let arbiter = &self.arbiters[self.next_arbiter];
+        self.next_arbiter = (self.next_arbiter + 1) % self.arbiters.len();
+
+        let config = self.config.clone();
+        let addr = ArenaActor::start_in_arbiter(arbiter, move |_| {
+            ArenaActor::new(
+                BolasArena::new(arena_id, &config),
+                Duration::from_millis(config.bolas_refresh_rate_ms),
+            )
+        });
+
+        RegisteredArena { addr, clients: 0 }
+    }
+
+    /// Forgets a client which failed to connect to its arena, dropping the
+    /// arena if the client was the only one in it
+    fn forget_client(&mut self, arena_id: Uuid, client_id: Uuid) {
+        let Some(arena) = self.arenas.get_mut(&arena_id) else {
+            return;
+        };
+
+        log::warn!("Client failed to connect to bolas arena"; "arena" => %arena_id, "client" => %client_id);
+        arena.clients -= 1;
+        if arena.clients == 0 {
+            self.arenas.remove(&arena_id);
+        }
+    }
+}
+
+impl Actor for ArenaRegistry {
+    type Context = Context<Self>;
+
+    fn started(&mut self, _: &mut Self::Context) {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        self.arbiters = (0..threads).map(|_| Arbiter::new().handle()).collect();
+    }
+}
+
+impl Handler<JoinArena> for ArenaRegistry {
+    type Result = ResponseActFuture<Self, Result<(ArenaHandle, PhysicsParams), MailboxError>>;
+
+    fn handle(&mut self, msg: JoinArena, _: &mut Self::Context) -> Self::Result {
+        let arena_id = msg.arena_id.unwrap_or_else(Uuid::new_v4);
+        if !self.arenas.contains_key(&arena_id) {
+            let arena = self.create_arena(arena_id);
+            self.arenas.insert(arena_id, arena);
+        }
+
+        let arena = self.arenas.get_mut(&arena_id).unwrap();
+        arena.clients += 1;
+        let connected = arena.addr.send(Connect {
+            client_id: msg.client_id,
+            recipient: msg.recipient,
+            rejections: msg.rejections,
+        });
+
+        log::debug!(
+            "Registered client with bolas arena";
+            "arena" => %arena_id,
+            "client" => %msg.client_id,
+            "clients" => arena.clients,
+        );
+
+        let handle = ArenaHandle {
+            id: arena_id,
+            addr: arena.addr.clone(),
+        };
+        let client_id = msg.client_id;
+        Box::pin(connected.into_actor(self).map(move |result, act, _| {
+            let physics = result.inspect_err(|_| act.forget_client(arena_id, client_id))?;
+            Ok((handle, physics))
+        }))
+    }
+}
+
+impl Handler<LeaveArena> for ArenaRegistry {
+    type Result = ();
+
+    fn handle(&mut self, msg: LeaveArena, _: &mut Self::Context) {
+        let Some(arena) = self.arenas.get_mut(&msg.arena_id) else {
+            log::error!("Client left unknown bolas arena"; "arena" => %msg.arena_id, "client" => %msg.client_id);
+            return;
+        };
+
+        arena.clients -= 1;
+        arena.addr.do_send(Disconnect {
+            client_id: msg.client_id,
+        });
+
+        if arena.clients == 0 {
+            self.arenas.remove(&msg.arena_id);
+        }
+    }
+}
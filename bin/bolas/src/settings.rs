@@ -1,22 +1,77 @@
 use crate::collisions::CollisionDetectionAlgorithm;
-use crate::utils::bootstrap_to_io_error;
+use crate::obstacles::ArenaLayout;
+use crate::physics::{Boundaries, ForceFields, Integrator, Materials};
 use foundations::settings::{net::SocketAddr, settings};
 use foundations::telemetry::settings::TelemetrySettings;
 use std::convert::TryFrom;
-use std::convert::TryInto;
 use std::io;
 use std::path::PathBuf;
 
 #[settings]
 pub(crate) struct BolasSettings {
     /// Interval in milliseconds at which the bolas state is
-    /// updated and sent to the websocket client
+    /// sent to the websocket clients
     #[serde(default = "default_bolas_refresh_rate_ms")]
     pub(crate) bolas_refresh_rate_ms: u64,
 
+    /// Precision in pixels positions of moving bolas are rounded to in the
+    /// state updates sent between keyframes, and in pixels per second for
+    /// their velocities
+    #[serde(default = "default_state_precision")]
+    pub(crate) state_precision: f64,
+
+    /// Number of state updates sent to a client between each keyframe
+    /// carrying the whole arena state, the updates in between only carry
+    /// what's changed
+    #[serde(default = "default_keyframe_interval")]
+    pub(crate) keyframe_interval: u32,
+
+    /// Number of invalid messages a client may send before it's
+    /// disconnected, each one is answered with an error until then
+    #[serde(default = "default_max_protocol_violations")]
+    pub(crate) max_protocol_violations: u32,
+
+    /// Length in milliseconds of each step of the physics simulation,
+    /// independent of how often the state is sent to clients
+    #[serde(default = "default_physics_timestep_ms")]
+    pub(crate) physics_timestep_ms: u64,
+
+    /// Most substeps a physics timestep is split into when bolas move fast
+    /// enough to pass through each other within a single timestep, setting
+    /// this to 1 disables substepping
+    #[serde(default = "default_max_physics_substeps")]
+    pub(crate) max_physics_substeps: u32,
+
+    /// Numerical integration method used to move bolas each physics step
+    pub(crate) integrator: Integrator,
+
     /// Algorithm to use when detecting collisions
     pub(crate) collision_detection_algorithm: CollisionDetectionAlgorithm,
 
+    /// Algorithm checked against brute force when the collision detection
+    /// algorithm is `verify`
+    pub(crate) verified_collision_detection_algorithm: CollisionDetectionAlgorithm,
+
+    /// Whether to query for collisions between bolas in parallel, which
+    /// helps keep arenas with many thousands of bolas within the physics timestep
+    pub(crate) parallel_collision_detection: bool,
+
+    /// Gravity and other force fields new arenas are created with, the
+    /// client creating an arena may replace them with its own
+    pub(crate) force_fields: ForceFields,
+
+    /// Drag, friction and restitution new arenas are created with, the
+    /// client creating an arena may replace them with its own
+    pub(crate) materials: Materials,
+
+    /// What happens to bolas reaching each edge of new arenas, the client
+    /// creating an arena may replace them with its own
+    pub(crate) boundaries: Boundaries,
+
+    /// Static obstacles new arenas are created with, any client may add
+    /// more of their own
+    pub(crate) arena_layout: ArenaLayout,
+
     /// Path to folder containing static files to be served
     pub(crate) static_file_path: PathBuf,
 
@@ -60,42 +115,125 @@ fn default_bolas_refresh_rate_ms() -> u64 {
     32
 }
 
+fn default_state_precision() -> f64 {
+    0.1
+}
+
+fn default_keyframe_interval() -> u32 {
+    60
+}
+
+fn default_max_protocol_violations() -> u32 {
+    10
+}
+
+fn default_physics_timestep_ms() -> u64 {
+    16
+}
+
+fn default_max_physics_substeps() -> u32 {
+    8
+}
+
 #[derive(Clone)]
 pub(crate) struct BolasConfig {
     /// Interval in milliseconds at which the bolas state is
-    /// updated and sent to the websocket client
+    /// sent to the websocket clients
     pub(crate) bolas_refresh_rate_ms: u64,
 
+    /// Precision positions and velocities are rounded to between keyframes
+    pub(crate) state_precision: f64,
+
+    /// Number of state updates sent between each keyframe
+    pub(crate) keyframe_interval: u32,
+
+    /// Number of invalid messages a client may send before it's disconnected
+    pub(crate) max_protocol_violations: u32,
+
+    /// Length in milliseconds of each step of the physics simulation
+    pub(crate) physics_timestep_ms: u64,
+
+    /// Most substeps a physics timestep is split into
+    pub(crate) max_physics_substeps: u32,
+
+    /// Numerical integration method used to move bolas each physics step
+    pub(crate) integrator: Integrator,
+
     /// Path to folder containing static files to be served
     pub(crate) static_file_path: PathBuf,
 
-    /// Factor by which the velocity users release balls with
-    /// (length of pull line in pixels) is divided to get the
-    /// actual velocity used
-    pub(crate) velocity_scaling_factor: i32,
-
     /// Algorithm to use when detecting collisions
     pub(crate) collision_detection_algorithm: CollisionDetectionAlgorithm,
+
+    /// Algorithm checked against brute force in `verify` mode
+    pub(crate) verified_collision_detection_algorithm: CollisionDetectionAlgorithm,
+
+    /// Whether to query for collisions between bolas in parallel
+    pub(crate) parallel_collision_detection: bool,
+
+    /// Gravity and other force fields new arenas are created with
+    pub(crate) force_fields: ForceFields,
+
+    /// Drag, friction and restitution new arenas are created with
+    pub(crate) materials: Materials,
+
+    /// What happens to bolas reaching each edge of new arenas
+    pub(crate) boundaries: Boundaries,
+
+    /// Static obstacles new arenas are created with
+    pub(crate) arena_layout: ArenaLayout,
 }
 
 impl TryFrom<&BolasSettings> for BolasConfig {
     type Error = io::Error;
 
     fn try_from(args: &BolasSettings) -> Result<Self, Self::Error> {
-        // I've found that this is a good factor to divide the velocity
-        // at which balls are released (number of pixels the user dragged
-        // before releasing) to make the experience look reasonable
-        let velocity_scaling_factor = args
-            .bolas_refresh_rate_ms
-            .try_into()
-            .map(|r: i32| 256 / r)
-            .map_err(bootstrap_to_io_error)?;
+        if args.bolas_refresh_rate_ms == 0 || args.physics_timestep_ms == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "bolas_refresh_rate_ms and physics_timestep_ms must be greater than zero",
+            ));
+        }
+
+        if !(args.state_precision.is_finite() && args.state_precision > 0.)
+            || args.keyframe_interval == 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "state_precision and keyframe_interval must be greater than zero",
+            ));
+        }
+
+        if args.max_protocol_violations == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "max_protocol_violations must be greater than zero",
+            ));
+        }
+
+        if let CollisionDetectionAlgorithm::Verify = args.verified_collision_detection_algorithm {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "verified_collision_detection_algorithm can't itself be verify",
+            ));
+        }
 
         Ok(Self {
             bolas_refresh_rate_ms: args.bolas_refresh_rate_ms,
+            state_precision: args.state_precision,
+            keyframe_interval: args.keyframe_interval,
+            max_protocol_violations: args.max_protocol_violations,
+            physics_timestep_ms: args.physics_timestep_ms,
+            max_physics_substeps: args.max_physics_substeps,
+            integrator: args.integrator,
             static_file_path: args.static_file_path.clone(),
-            velocity_scaling_factor,
             collision_detection_algorithm: args.collision_detection_algorithm,
+            verified_collision_detection_algorithm: args.verified_collision_detection_algorithm,
+            parallel_collision_detection: args.parallel_collision_detection,
+            force_fields: args.force_fields.clone(),
+            materials: args.materials,
+            boundaries: args.boundaries,
+            arena_layout: args.arena_layout.clone(),
         })
     }
 }
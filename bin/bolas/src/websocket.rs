@@ -1,58 +1,251 @@
-use actix::{Actor, ActorContext, AsyncContext, StreamHandler};
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, ContextFutureSpawner, Handler,
+    StreamHandler, WrapFuture,
+};
 use actix_web::{web, Error, HttpRequest, HttpResponse, Result};
 use actix_web_actors::ws;
 use foundations::telemetry::log;
 use serde::Deserialize;
+use uuid::Uuid;
 
 use crate::{
-    bolas::{Bola, BolasArena},
+    arena::{ArenaStateUpdate, ClientCommand, CommandRejected},
+    bolas::{Bola, BolaId, Point, Vector},
+    delta::DeltaTracker,
+    obstacles::Obstacle,
+    physics::{Boundaries, ForceFields, Materials, PhysicsParams},
+    protocol::{
+        ArenaEvent, EncodedMessage, Encoding, ErrorCode, MessageId, MessageIdProbe, ServerMessage,
+        PROTOCOL_VERSION,
+    },
+    registry::{ArenaHandle, ArenaRegistry, JoinArena, LeaveArena},
     settings::BolasConfig,
+    version::VersionInfo,
 };
 
+#[derive(Deserialize)]
+pub(crate) struct ArenaQueryParams {
+    /// Id of the arena to join, a new arena is created if none is given
+    arena: Option<Uuid>,
+}
+
 pub(crate) async fn serve_websockets(
     req: HttpRequest,
     stream: web::Payload,
+    query: web::Query<ArenaQueryParams>,
+    registry: web::Data<Addr<ArenaRegistry>>,
     config: web::Data<BolasConfig>,
+    version_info: web::Data<VersionInfo>,
 ) -> Result<HttpResponse, Error> {
+    let encoding = Encoding::negotiate(&req);
     let actor = BolasWebsocketActor {
-        bolas_state: BolasArena::new(
-            config.bolas_refresh_rate_ms,
-            config.velocity_scaling_factor,
-            config.collision_detection_algorithm,
-        ),
+        id: Uuid::new_v4(),
+        requested_arena_id: query.arena,
+        registry: registry.get_ref().clone(),
+        arena: None,
+        encoding: encoding.unwrap_or_default(),
+        delta_tracker: DeltaTracker::new(config.state_precision, config.keyframe_interval),
+        server_version: version_info.version,
+        sent_physics: None,
+        protocol_violations: 0,
+        max_protocol_violations: config.max_protocol_violations,
     };
 
-    ws::start(actor, &req, stream)
+    // Only echo a subprotocol back when the client asked for one
+    let builder = ws::WsResponseBuilder::new(actor, &req, stream);
+    match encoding {
+        Some(encoding) => builder.protocols(&[encoding.subprotocol()]).start(),
+        None => builder.start(),
+    }
 }
 
 struct BolasWebsocketActor {
-    bolas_state: BolasArena,
+    id: Uuid,
+    requested_arena_id: Option<Uuid>,
+    registry: Addr<ArenaRegistry>,
+    arena: Option<ArenaHandle>,
+
+    /// Encoding the arena state is sent to the client in
+    encoding: Encoding,
+
+    /// What the client has been sent of the arena state so far
+    delta_tracker: DeltaTracker,
+
+    server_version: &'static str,
+
+    /// Physics the client was last told the arena is simulated with, which
+    /// is `None` until the client has joined and been sent its hello
+    sent_physics: Option<PhysicsParams>,
+
+    /// Number of invalid messages the client has sent, it's disconnected
+    /// once this reaches the maximum
+    protocol_violations: u32,
+    max_protocol_violations: u32,
 }
 
 #[derive(Deserialize)]
-enum ClientMessage {
-    SetCanvasDimensions { height: i32, width: i32 },
+pub(crate) enum ClientMessage {
+    SetCanvasDimensions {
+        height: i32,
+        width: i32,
+    },
     NewBola(Bola),
+    /// Throws a chain of bolas, each tied to the next by a rope, defaulting
+    /// to the distance between them when no length is given
+    NewTetheredBolas {
+        bolas: Vec<Bola>,
+        #[serde(default)]
+        length: Option<f64>,
+    },
+    /// Ties a pair of bolas, identified by their ids, together with a
+    /// spring, with a rest length of the distance between them when no
+    /// length is given
+    NewSpring {
+        one: BolaId,
+        two: BolaId,
+        #[serde(default)]
+        length: Option<f64>,
+        stiffness: f64,
+        #[serde(default)]
+        damping: f64,
+    },
+    /// Holds a pair of bolas, identified by their ids, a fixed distance
+    /// apart, defaulting to the distance between them
+    NewDistanceJoint {
+        one: BolaId,
+        two: BolaId,
+        #[serde(default)]
+        length: Option<f64>,
+    },
+    /// Pins a bola, identified by its id, to a fixed point, holding it in
+    /// place if no anchor is given
+    NewPinJoint {
+        bola: BolaId,
+        #[serde(default)]
+        anchor: Option<Point>,
+        #[serde(default)]
+        length: Option<f64>,
+    },
+    /// Removes a bola, along with anything tying it to other bolas
+    DeleteBola {
+        bola: BolaId,
+    },
+    /// Removes every bola from the arena, only honoured for the client
+    /// which owns the arena
+    ClearArena,
+    /// Picks up a bola, which then follows `DragBola` messages from the
+    /// client, unaffected by anything else, until it's released
+    GrabBola {
+        bola: BolaId,
+    },
+    /// Moves a bola the client has grabbed to where its pointer now is
+    DragBola {
+        bola: BolaId,
+        #[serde(rename = "c")]
+        position: Point,
+    },
+    /// Lets go of a bola the client has grabbed, throwing it with the given
+    /// velocity in pixels per second
+    ReleaseBola {
+        bola: BolaId,
+        #[serde(rename = "v")]
+        velocity: Vector,
+    },
+    /// Adds a static segment, rectangle or peg for bolas to bounce off
+    NewObstacle(Obstacle),
+    /// Replaces the arena's gravity and force fields, only honoured for the
+    /// client which owns the arena
+    SetForceFields(ForceFields),
+    /// Replaces the arena's drag, friction and restitution, only honoured
+    /// for the client which owns the arena
+    SetMaterials(Materials),
+    /// Replaces what happens to bolas reaching each edge of the arena, only
+    /// honoured for the client which owns the arena
+    SetBoundaries(Boundaries),
+}
+
+/// Client message carrying an id alongside it, as in
+/// `{"id": 1, "DeleteBola": {"bola": 2}}`
+#[derive(Deserialize)]
+struct ClientEnvelope {
+    #[serde(rename = "id")]
+    _id: MessageId,
+
+    #[serde(flatten)]
+    message: ClientMessage,
 }
 
 impl BolasWebsocketActor {
-    fn start_refresh_loop(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
-        let arena_id = self.bolas_state.get_id();
-        log::info!("Created new bolas arena"; "arena" => %arena_id);
-
-        ctx.run_interval(self.bolas_state.get_refresh_rate(), move |act, ctx| {
-            act.bolas_state.update_state();
-            let message = match serde_json::to_string(&act.bolas_state) {
-                Ok(m) => m,
-                Err(e) => {
-                    log::error!("Failed to serialize bolas state to send to client"; "arena" => %arena_id, "error" => %e);
+    fn join_arena(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        let join = JoinArena {
+            arena_id: self.requested_arena_id,
+            client_id: self.id,
+            recipient: ctx.address().recipient(),
+            rejections: ctx.address().recipient(),
+        };
+
+        // Wait for the join to complete so that no client messages are
+        // handled before we know which arena to forward them to
+        self.registry
+            .send(join)
+            .into_actor(self)
+            .map(|result, act, ctx| match result {
+                Ok(Ok((arena, physics))) => {
+                    log::info!("Client connected to bolas arena"; "arena" => %arena.id, "client" => %act.id);
+
+                    // Greeted before anything else is sent, including
+                    // replies to any messages the client sent while joining
+                    act.send(
+                        &ServerMessage::Hello {
+                            protocol: PROTOCOL_VERSION,
+                            arena: arena.id,
+                            server: act.server_version,
+                            physics: &physics,
+                        },
+                        ctx,
+                    );
+                    act.arena = Some(arena);
+                    act.sent_physics = Some(physics);
+                }
+                Ok(Err(e)) | Err(e) => {
+                    log::error!("Failed to join bolas arena"; "client" => %act.id, "error" => %e);
                     ctx.stop();
-                    return;
                 }
-            };
+            })
+            .wait(ctx);
+    }
 
-            ctx.text(message);
-        });
+    fn send(&self, message: &ServerMessage, ctx: &mut ws::WebsocketContext<Self>) {
+        match self.encoding.encode(message) {
+            Ok(EncodedMessage::Text(text)) => ctx.text(text),
+            Ok(EncodedMessage::Binary(bytes)) => ctx.binary(bytes),
+            Err(e) => {
+                log::error!("Failed to serialize message to send to client"; "client" => %self.id, "encoding" => ?self.encoding, "error" => %e);
+            }
+        }
+    }
+
+    /// Replies to a message the client shouldn't have sent with an error,
+    /// rather than dropping the connection over a single bad message, and
+    /// only disconnects the client once it has sent too many of them
+    fn reject(
+        &mut self,
+        code: ErrorCode,
+        id: Option<MessageId>,
+        message: String,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) {
+        self.protocol_violations += 1;
+        self.send(&ServerMessage::Error { code, id, message }, ctx);
+
+        if self.protocol_violations >= self.max_protocol_violations {
+            log::warn!("Disconnecting client after too many protocol violations"; "client" => %self.id, "violations" => self.protocol_violations);
+            ctx.close(Some(ws::CloseReason {
+                code: ws::CloseCode::Policy,
+                description: Some("Too many protocol violations".to_owned()),
+            }));
+            ctx.stop();
+        }
     }
 }
 
@@ -60,7 +253,57 @@ impl Actor for BolasWebsocketActor {
     type Context = ws::WebsocketContext<Self>;
 
     fn started(&mut self, ctx: &mut Self::Context) {
-        self.start_refresh_loop(ctx);
+        self.join_arena(ctx);
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        if let Some(arena) = &self.arena {
+            self.registry.do_send(LeaveArena {
+                arena_id: arena.id,
+                client_id: self.id,
+            });
+        }
+    }
+}
+
+impl Handler<ArenaStateUpdate> for BolasWebsocketActor {
+    type Result = ();
+
+    fn handle(&mut self, msg: ArenaStateUpdate, ctx: &mut Self::Context) {
+        let snapshot = &msg.0;
+
+        if self.sent_physics.as_ref() != Some(&snapshot.physics) {
+            self.send(
+                &ServerMessage::Event(ArenaEvent::PhysicsChanged {
+                    physics: &snapshot.physics,
+                }),
+                ctx,
+            );
+            self.sent_physics = Some(snapshot.physics.clone());
+        }
+
+        if let Some(update) = self.delta_tracker.update(snapshot) {
+            self.send(&ServerMessage::State(update), ctx);
+        }
+    }
+}
+
+impl Handler<CommandRejected> for BolasWebsocketActor {
+    type Result = ();
+
+    /// Messages the arena refuses are relayed to the client, but unlike
+    /// invalid messages they don't count as protocol violations, as they're
+    /// often down to a race with other clients, like deleting a bola someone
+    /// else just deleted
+    fn handle(&mut self, msg: CommandRejected, ctx: &mut Self::Context) {
+        self.send(
+            &ServerMessage::Error {
+                code: msg.code,
+                id: msg.id,
+                message: msg.message,
+            },
+            ctx,
+        );
     }
 }
 
@@ -74,44 +317,70 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for BolasWebsocketAct
             Ok(msg) => msg,
         };
 
-        let client_message_text = match msg {
-            ws::Message::Text(text) => text,
+        // Clients may send messages in either encoding, whichever they
+        // negotiated for the state they receive
+        let (encoding, message) = match msg {
+            ws::Message::Text(text) => (Encoding::Json, text.into_bytes()),
+            ws::Message::Binary(bytes) => (Encoding::MessagePack, bytes),
+            ws::Message::Ping(bytes) => {
+                ctx.pong(&bytes);
+                return;
+            }
+            ws::Message::Pong(_) | ws::Message::Nop => return,
             ws::Message::Close(_) => {
-                log::debug!("Client closed the connection, exiting actor"; "arena" => %self.bolas_state.get_id());
+                log::debug!("Client closed the connection, exiting actor"; "client" => %self.id);
                 ctx.stop();
                 return;
             }
             _ => {
-                log::error!("Websocket actor received unexpected message type"; "arena" => %self.bolas_state.get_id(), "message_type" => ?msg);
-                ctx.stop();
+                log::warn!("Websocket actor received unexpected message type"; "client" => %self.id, "message_type" => ?msg);
+                self.reject(
+                    ErrorCode::UnsupportedFrame,
+                    None,
+                    "Expected a text or binary frame".to_owned(),
+                    ctx,
+                );
                 return;
             }
         };
 
-        let Ok(client_message) = serde_json::from_slice(client_message_text.as_bytes()) else {
-            log::error!(
-                "Failed to parse message from client";
-                "arena" => %self.bolas_state.get_id(),
-                "message" => ?client_message_text,
-            );
-            ctx.stop();
-            return;
+        // The id is read on its own first so that it can be sent back even if
+        // the rest of the message is invalid. Messages without one are sent
+        // bare, as they were before ids were added.
+        let id = encoding
+            .decode::<MessageIdProbe>(&message)
+            .ok()
+            .and_then(|m| m.id);
+        let client_message = match id {
+            Some(_) => encoding
+                .decode::<ClientEnvelope>(&message)
+                .map(|e| e.message),
+            None => encoding.decode::<ClientMessage>(&message),
         };
 
-        match client_message {
-            ClientMessage::SetCanvasDimensions { height, width } => {
-                log::debug!(
-                    "Updating canvas dimensions";
-                    "arena" => %self.bolas_state.get_id(),
-                    "height" => height,
-                    "width" => width,
+        let client_message = match client_message {
+            Ok(m) => m,
+            Err(e) => {
+                log::warn!(
+                    "Failed to parse message from client";
+                    "client" => %self.id,
+                    "id" => ?id,
+                    "error" => %e,
                 );
-                self.bolas_state.set_canvas_dimensions(height, width);
-            }
-            ClientMessage::NewBola(bola) => {
-                log::debug!("Adding new bola"; "arena" => %self.bolas_state.get_id(), "bola" => ?bola);
-                self.bolas_state.add_bola(bola);
+                self.reject(ErrorCode::InvalidMessage, id, e.to_string(), ctx);
+                return;
             }
-        }
+        };
+
+        let Some(arena) = &self.arena else {
+            log::error!("Received message from client before joining an arena"; "client" => %self.id);
+            return;
+        };
+
+        arena.addr.do_send(ClientCommand {
+            client_id: self.id,
+            id,
+            message: client_message,
+        });
     }
 }
@@ -1,20 +1,29 @@
 const windowResizeDebounceTimeout = 50; // milliseconds
-const bolaRadius = 20;
 const defaultBackgroundColor = "#002d72";
 const defaultBolaLineColor = "#da291c";
 const defaultBolaColor = "#ffffff";
+const defaultObstacleColor = "#ffb81c";
+const tetheredBolasSpacing = 80; // pixels
+const maxExtrapolation = 0.25; // seconds
+const protocolVersion = 1;
 
 class BolasState {
     constructor() {
         this.bolas = [];
+        this.links = [];
+        this.obstacles = [];
+        this.precision = 1;
+        this.receivedAt = performance.now();
         this.bolasUpdated = true;
         this.backgroundColor = defaultBackgroundColor;
         this.bolaLineColor = defaultBolaLineColor;
         this.bolaColor = defaultBolaColor;
+        this.obstacleColor = defaultObstacleColor;
         this.newBallStart = null;
         this.newBallHold = null;
         this.lastLineStart = null;
         this.lastLineEnd = null;
+        this.grabbed = null;
     }
 }
 
@@ -42,13 +51,59 @@ function drawBallLine(ctx, bolasState) {
     bolasState.lastLineEnd = bolasState.newBallHold;
 }
 
+function drawObstacles(ctx, bolasState) {
+    ctx.fillStyle = bolasState.obstacleColor;
+
+    for (let o of bolasState.obstacles) {
+        if (o.k == "segment") {
+            drawLine(
+                ctx,
+                { x: o.x1, y: o.y1 },
+                { x: o.x2, y: o.y2 },
+                bolasState.obstacleColor,
+            );
+        } else if (o.k == "rectangle") {
+            ctx.fillRect(o.x, o.y, o.width, o.height);
+        } else if (o.k == "peg") {
+            ctx.beginPath();
+            ctx.arc(o.x, o.y, o.radius, 0, 2 * Math.PI);
+            ctx.fill();
+        }
+    }
+}
+
+// Moves a bola along by its velocity for the time since the last update, so
+// bolas keep moving smoothly when updates arrive late or unevenly
+function extrapolate(bola, elapsed) {
+    return {
+        x: bola.c.x + bola.v.vel_x * elapsed,
+        y: bola.c.y + bola.v.vel_y * elapsed,
+    };
+}
+
 function drawBolas(ctx, bolasState) {
+    let elapsed = Math.min(
+        (performance.now() - bolasState.receivedAt) / 1000,
+        maxExtrapolation,
+    );
+
+    // Links refer to bolas by their ids
+    let centers = new Map(
+        bolasState.bolas.map((b) => [b.i, extrapolate(b, elapsed)]),
+    );
+    for (let l of bolasState.links) {
+        // Pin joints tie a bola to a fixed point rather than another bola
+        let end = l.k == "pin_joint" ? l.p : centers.get(l.b);
+        drawLine(ctx, centers.get(l.a), end, bolasState.bolaColor);
+    }
+
     for (let b of bolasState.bolas) {
+        let c = extrapolate(b, elapsed);
         ctx.fillStyle = bolasState.bolaColor;
         ctx.strokeStyle = bolasState.bolaColor;
 
         ctx.beginPath();
-        ctx.arc(b.c.x, b.c.y, bolaRadius, 0, 2 * Math.PI);
+        ctx.arc(c.x, c.y, b.r, 0, 2 * Math.PI);
         ctx.stroke();
         ctx.fill();
     }
@@ -66,11 +121,15 @@ function fullRedraw(canvas, bolasState) {
         drawBallLine(ctx, bolasState);
     }
 
+    drawObstacles(ctx, bolasState);
     drawBolas(ctx, bolasState);
 }
 
 function draw(canvas, bolasState) {
-    if (bolasState.bolasUpdated) {
+    let moving = bolasState.bolas.some(
+        (b) => b.v.vel_x != 0 || b.v.vel_y != 0,
+    );
+    if (bolasState.bolasUpdated || moving) {
         fullRedraw(canvas, bolasState);
         return;
     }
@@ -133,14 +192,66 @@ function setupCanvasEvents(
     );
 
     canvas[downEvent] = (e) => {
-        bolasState.newBallStart = { x: getX(e), y: getY(e) };
+        let pointer = { x: getX(e), y: getY(e) };
+        let bola = bolaAt(bolasState, pointer);
+
+        // Pressing on an existing bola picks it up rather than throwing a
+        // new one
+        if (bola != null) {
+            bolasState.grabbed = {
+                id: bola.i,
+                position: pointer,
+                time: performance.now(),
+                velocity: { vel_x: 0, vel_y: 0 },
+            };
+            socket.send(JSON.stringify({ GrabBola: { bola: bola.i } }));
+            return;
+        }
+
+        bolasState.newBallStart = pointer;
     };
 
     canvas[moveEvent] = (e) => {
-        bolasState.newBallHold = { x: getX(e), y: getY(e) };
+        let pointer = { x: getX(e), y: getY(e) };
+        let grabbed = bolasState.grabbed;
+
+        if (grabbed != null) {
+            // Keep track of how fast the bola is being dragged, so it can
+            // be thrown with that velocity when it's released
+            let now = performance.now();
+            let elapsed = (now - grabbed.time) / 1000;
+            if (elapsed > 0) {
+                grabbed.velocity = {
+                    vel_x: (pointer.x - grabbed.position.x) / elapsed,
+                    vel_y: (pointer.y - grabbed.position.y) / elapsed,
+                };
+            }
+            grabbed.position = pointer;
+            grabbed.time = now;
+
+            socket.send(
+                JSON.stringify({ DragBola: { bola: grabbed.id, c: pointer } }),
+            );
+            return;
+        }
+
+        bolasState.newBallHold = pointer;
     };
 
-    canvas[upEvent] = () => {
+    canvas[upEvent] = (e) => {
+        if (bolasState.grabbed != null) {
+            socket.send(
+                JSON.stringify({
+                    ReleaseBola: {
+                        bola: bolasState.grabbed.id,
+                        v: bolasState.grabbed.velocity,
+                    },
+                }),
+            );
+            bolasState.grabbed = null;
+            return;
+        }
+
         if (bolasState.newBallStart != null) {
             let velX = Math.floor(
                 bolasState.newBallStart.x - bolasState.newBallHold.x,
@@ -149,14 +260,28 @@ function setupCanvasEvents(
                 bolasState.newBallStart.y - bolasState.newBallHold.y,
             );
 
-            socket.send(
-                JSON.stringify({
-                    NewBola: {
-                        c: bolasState.newBallHold,
-                        v: { vel_x: velX, vel_y: velY },
-                    },
-                }),
-            );
+            if (e.shiftKey) {
+                socket.send(
+                    JSON.stringify({
+                        NewTetheredBolas: {
+                            bolas: newTetheredBolas(
+                                bolasState.newBallHold,
+                                velX,
+                                velY,
+                            ),
+                        },
+                    }),
+                );
+            } else {
+                socket.send(
+                    JSON.stringify({
+                        NewBola: {
+                            c: bolasState.newBallHold,
+                            v: { vel_x: velX, vel_y: velY },
+                        },
+                    }),
+                );
+            }
 
             bolasState.newBallStart = null;
             bolasState.newBallHold = null;
@@ -164,8 +289,41 @@ function setupCanvasEvents(
     };
 }
 
+function bolaAt(bolasState, point) {
+    return bolasState.bolas.find(
+        (b) => Math.hypot(b.c.x - point.x, b.c.y - point.y) <= b.r,
+    );
+}
+
+// Places a pair of bolas either side of the release point, across the
+// direction they're thrown in, spinning around each other as they fly
+function newTetheredBolas(center, velX, velY) {
+    let speed = Math.hypot(velX, velY) || 1;
+    let along = { x: velX / speed, y: velY / speed };
+    let across = { x: -along.y, y: along.x };
+    let spin = speed / 4;
+
+    return [1, -1].map((side) => ({
+        c: {
+            x: center.x + (across.x * side * tetheredBolasSpacing) / 2,
+            y: center.y + (across.y * side * tetheredBolasSpacing) / 2,
+        },
+        v: {
+            vel_x: Math.floor(velX + along.x * side * spin),
+            vel_y: Math.floor(velY + along.y * side * spin),
+        },
+    }));
+}
+
 function setupDesktopEvents(canvas, bolasState, socket) {
     console.log("Setting up bolas events for desktop browswer");
+    canvas.ondblclick = (e) => {
+        let bola = bolaAt(bolasState, { x: e.x, y: e.y });
+        if (bola != null) {
+            socket.send(JSON.stringify({ DeleteBola: { bola: bola.i } }));
+        }
+    };
+
     setupCanvasEvents(
         canvas,
         bolasState,
@@ -196,8 +354,66 @@ function isMobile() {
     return "ontouchstart" in document.documentElement;
 }
 
+function getArenaUrl(arenaId) {
+    let url = new URL(location.href);
+    url.searchParams.set("arena", arenaId);
+    return url;
+}
+
+function applyStateUpdate(bolasState, update) {
+    if (update.k == "keyframe") {
+        bolasState.precision = update.precision;
+        bolasState.bolas = update.bolas;
+        bolasState.links = update.links;
+        bolasState.obstacles = update.obstacles;
+    } else {
+        applyDelta(bolasState, update);
+    }
+
+    bolasState.receivedAt = performance.now();
+    bolasState.bolasUpdated = true;
+}
+
+// Deltas only carry what's changed since the last update, with the
+// positions of moved bolas in multiples of the keyframe's precision
+function applyDelta(bolasState, delta) {
+    let removed = new Set(delta.removed || []);
+    let moved = new Map((delta.moved || []).map((m) => [m.i, m]));
+
+    bolasState.bolas = bolasState.bolas
+        .filter((b) => !removed.has(b.i))
+        .concat(delta.added || []);
+
+    for (let b of bolasState.bolas) {
+        let m = moved.get(b.i);
+        if (m != null) {
+            b.c = {
+                x: m.x * bolasState.precision,
+                y: m.y * bolasState.precision,
+            };
+            b.v = {
+                vel_x: m.vx * bolasState.precision,
+                vel_y: m.vy * bolasState.precision,
+            };
+        }
+    }
+
+    if (delta.links != null) {
+        bolasState.links = delta.links;
+    }
+
+    if (delta.obstacles != null) {
+        bolasState.obstacles = delta.obstacles;
+    }
+}
+
 function setupWebsocketEvents(canvas, bolasState) {
-    let server = location.origin.replace(/^http/, "ws") + "/ws";
+    let server = new URL(location.origin.replace(/^http/, "ws") + "/ws");
+    let arenaId = new URLSearchParams(location.search).get("arena");
+    if (arenaId != null) {
+        server.searchParams.set("arena", arenaId);
+    }
+
     let socket = new WebSocket(server);
 
     socket.onopen = (_) => {
@@ -214,9 +430,28 @@ function setupWebsocketEvents(canvas, bolasState) {
     };
 
     socket.onmessage = (e) => {
-        let bolas = JSON.parse(e.data).bolas;
-        bolasState.bolas = bolas;
-        bolasState.bolasUpdated = true;
+        let message = JSON.parse(e.data);
+
+        if (message.t == "hello") {
+            if (message.protocol != protocolVersion) {
+                console.log(
+                    `Server speaks protocol version ${message.protocol}, expected ${protocolVersion}`,
+                );
+            }
+
+            // Put the arena id in the address bar so the link can be shared
+            // with others to play in the same arena
+            if (arenaId != message.arena) {
+                arenaId = message.arena;
+                history.replaceState(null, "", getArenaUrl(arenaId));
+            }
+        } else if (message.t == "state") {
+            applyStateUpdate(bolasState, message);
+        } else if (message.t == "error") {
+            console.log(
+                `Server rejected message ${message.id ?? ""}: ${message.code}: ${message.message}`,
+            );
+        }
     };
 
     socket.onclose = (_) => {